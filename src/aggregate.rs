@@ -0,0 +1,436 @@
+use crate::api_util::{self, ApiCall, PiHoleBackend, PiHoleHost};
+use crate::commands::ApiCommands;
+use crate::error::HostError;
+use crate::table::{TableCell, ToTableCell, ToTableRows};
+use cli_table::Color;
+use pi_hole_api::api_types::SummaryRaw;
+use pi_hole_api::errors::APIError;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Entries a Pi-Hole returns for a top list when no count is given
+const DEFAULT_TOP_COUNT: u32 = 10;
+
+/// How many times the requested count of a top list is fetched from each host. An entry just
+/// outside the top of every host can still be in the top of their totals.
+const TOP_COUNT_FACTOR: u32 = 3;
+
+/// A command whose results `--aggregate` can combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// `summary` and `summary-raw`, both totalled from the raw counters
+    Summary,
+    TopItems {
+        count: Option<u32>,
+    },
+    TopClients {
+        count: Option<u32>,
+    },
+    TopClientsBlocked {
+        count: Option<u32>,
+    },
+    QueryTypes,
+    ForwardDestinations {
+        unsorted: bool,
+    },
+}
+
+impl Aggregation {
+    /// How the results of `command` are combined, or `None` if `--aggregate` does not support it
+    pub fn of(command: &ApiCommands) -> Option<Self> {
+        Some(match command {
+            ApiCommands::Summary | ApiCommands::SummaryRaw => Self::Summary,
+            ApiCommands::TopItems { count } => Self::TopItems { count: *count },
+            ApiCommands::TopClients { count } => Self::TopClients { count: *count },
+            ApiCommands::TopClientsBlocked { count } => Self::TopClientsBlocked { count: *count },
+            ApiCommands::QueryTypes => Self::QueryTypes,
+            ApiCommands::ForwardDestinations { unsorted } => Self::ForwardDestinations {
+                unsorted: *unsorted,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Call the hosts and combine the results of those which responded. Hosts are also asked for
+    /// their query volume where the percentages are weighted by it, and a host which fails either
+    /// call is left out. Returns the outcome of each host, and no aggregate if none responded.
+    pub fn call(
+        self,
+        hosts: &[PiHoleHost],
+        parallelism: usize,
+        fail_fast: bool,
+    ) -> (Option<Aggregate>, Vec<Result<(), HostError>>) {
+        let fleet = Fleet {
+            hosts,
+            parallelism,
+            fail_fast,
+        };
+        match self {
+            Self::Summary => fleet.total(
+                fleet.call(|api| api.get_unauthenticated_api().get_summary_raw()),
+                |summaries| Totals::Summary(sum_summaries(summaries)),
+            ),
+            Self::TopItems { count } => fleet.total(
+                fleet.call(|api| api.get_authenticated_api()?.get_top_items(&fetched(count))),
+                |items| Totals::TopItems {
+                    top_queries: rank(items.iter().map(|items| &items.top_queries), count),
+                    top_ads: rank(items.iter().map(|items| &items.top_ads), count),
+                },
+            ),
+            Self::TopClients { count } => fleet.total(
+                fleet.call(|api| {
+                    api.get_authenticated_api()?
+                        .get_top_clients(&fetched(count))
+                }),
+                |clients| Totals::TopClients {
+                    top_sources: rank(clients.iter().map(|clients| &clients.top_sources), count),
+                },
+            ),
+            Self::TopClientsBlocked { count } => fleet.total(
+                fleet.call(|api| {
+                    api.get_authenticated_api()?
+                        .get_top_clients_blocked(fetched(count))
+                }),
+                |clients| Totals::TopClientsBlocked {
+                    top_sources_blocked: rank(
+                        clients.iter().map(|clients| &clients.top_sources_blocked),
+                        count,
+                    ),
+                },
+            ),
+            // Query types are a share of the queries of every type
+            Self::QueryTypes => fleet.total(
+                fleet.with_volume(fleet.call(|api| api.get_authenticated_api()?.get_query_types())),
+                |hosts| Totals::QueryTypes {
+                    querytypes: weighted(
+                        hosts.iter().map(|(types, volume)| {
+                            (&types.querytypes, volume.dns_queries_all_types)
+                        }),
+                    ),
+                },
+            ),
+            // Forward destinations include the cache and blocklist, so are a share of every query
+            Self::ForwardDestinations { unsorted } => fleet.total(
+                fleet.with_volume(fleet.call(|api| {
+                    api.get_authenticated_api()?
+                        .get_forward_destinations(unsorted)
+                })),
+                |hosts| Totals::ForwardDestinations {
+                    forward_destinations: weighted(hosts.iter().map(|(destinations, volume)| {
+                        (&destinations.forward_destinations, volume.dns_queries_today)
+                    })),
+                },
+            ),
+        }
+    }
+}
+
+/// The hosts of an aggregated command, and how to call them
+struct Fleet<'a> {
+    hosts: &'a [PiHoleHost],
+    parallelism: usize,
+    fail_fast: bool,
+}
+
+impl Fleet<'_> {
+    fn call<T, F>(&self, call: F) -> Vec<Result<T, HostError>>
+    where
+        T: Send,
+        F: Fn(&dyn PiHoleBackend) -> Result<T, APIError> + Sync,
+    {
+        api_util::call_hosts(&ApiCall(call), self.hosts, self.parallelism, self.fail_fast)
+    }
+
+    /// Pair the result of each host with its query volume
+    fn with_volume<T: Send>(
+        &self,
+        results: Vec<Result<T, HostError>>,
+    ) -> Vec<Result<(T, SummaryRaw), HostError>> {
+        let volumes = self.call(|api| api.get_unauthenticated_api().get_summary_raw());
+        results
+            .into_iter()
+            .zip(volumes)
+            .map(|(result, volume)| Ok((result?, volume?)))
+            .collect()
+    }
+
+    /// Combine the results of the hosts which responded
+    fn total<T>(
+        &self,
+        results: Vec<Result<T, HostError>>,
+        combine: impl FnOnce(Vec<T>) -> Totals,
+    ) -> (Option<Aggregate>, Vec<Result<(), HostError>>) {
+        let (mut hosts, mut responded) = (Vec::new(), Vec::new());
+        let outcomes = self
+            .hosts
+            .iter()
+            .zip(results)
+            .map(|(host, result)| {
+                result.map(|data| {
+                    hosts.push(host.host.clone());
+                    responded.push(data);
+                })
+            })
+            .collect();
+        let aggregate = (!responded.is_empty()).then(|| Aggregate {
+            hosts,
+            totals: combine(responded),
+        });
+        (aggregate, outcomes)
+    }
+}
+
+/// Results of several hosts combined into one
+#[derive(Debug, Serialize)]
+pub struct Aggregate {
+    /// Hosts whose results were combined
+    pub hosts: Vec<String>,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Totals {
+    /// Counters summed across the hosts, with the percentage blocked recomputed from them
+    Summary(SummaryRaw),
+    TopItems {
+        top_queries: Vec<Ranked>,
+        top_ads: Vec<Ranked>,
+    },
+    TopClients {
+        top_sources: Vec<Ranked>,
+    },
+    TopClientsBlocked {
+        top_sources_blocked: Vec<Ranked>,
+    },
+    /// Percentages of each host weighted by its number of queries
+    QueryTypes {
+        querytypes: Vec<Share>,
+    },
+    ForwardDestinations {
+        forward_destinations: Vec<Share>,
+    },
+}
+
+/// An entry of a top list, largest count first
+#[derive(Debug, Serialize)]
+pub struct Ranked {
+    pub name: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Share {
+    pub name: String,
+    pub percentage: f64,
+}
+
+/// Sum the counters of each host. Hosts usually share their blocklists, so the domains being
+/// blocked is the largest of any host rather than the sum, as is the privacy level.
+fn sum_summaries(summaries: Vec<SummaryRaw>) -> SummaryRaw {
+    let sum = |field: fn(&SummaryRaw) -> u64| summaries.iter().map(field).sum::<u64>();
+    let max = |field: fn(&SummaryRaw) -> u64| summaries.iter().map(field).max().unwrap_or(0);
+    let dns_queries_today = sum(|s| s.dns_queries_today);
+    let ads_blocked_today = sum(|s| s.ads_blocked_today);
+    let enabled = summaries.iter().filter(|s| s.status == "enabled").count();
+    let status = if enabled == summaries.len() {
+        "enabled".to_owned()
+    } else if enabled == 0 {
+        "disabled".to_owned()
+    } else {
+        format!("{} of {} enabled", enabled, summaries.len())
+    };
+
+    SummaryRaw {
+        domains_being_blocked: max(|s| s.domains_being_blocked),
+        dns_queries_today,
+        ads_blocked_today,
+        ads_percentage_today: percentage(ads_blocked_today as f64, dns_queries_today as f64),
+        unique_domains: sum(|s| s.unique_domains),
+        queries_forwarded: sum(|s| s.queries_forwarded),
+        queries_cached: sum(|s| s.queries_cached),
+        clients_ever_seen: sum(|s| s.clients_ever_seen),
+        unique_clients: sum(|s| s.unique_clients),
+        dns_queries_all_types: sum(|s| s.dns_queries_all_types),
+        reply_nodata: sum(|s| s.reply_nodata),
+        reply_nxdomain: sum(|s| s.reply_nxdomain),
+        reply_cname: sum(|s| s.reply_cname),
+        reply_ip: sum(|s| s.reply_ip),
+        privacy_level: max(|s| s.privacy_level),
+        status,
+    }
+}
+
+fn percentage(part: f64, total: f64) -> f64 {
+    if total > 0.0 {
+        part / total * 100.0
+    } else {
+        0.0
+    }
+}
+
+/// Entries fetched from each host for a top list of `count` entries
+fn fetched(count: Option<u32>) -> Option<u32> {
+    Some(
+        count
+            .unwrap_or(DEFAULT_TOP_COUNT)
+            .saturating_mul(TOP_COUNT_FACTOR),
+    )
+}
+
+/// Add up the counts of each host and keep the largest `count` entries, or as many as a
+/// Pi-Hole returns by default
+fn rank<'a>(
+    hosts: impl Iterator<Item = &'a HashMap<String, u64>>,
+    count: Option<u32>,
+) -> Vec<Ranked> {
+    let mut totals: HashMap<&str, u64> = HashMap::new();
+    for counts in hosts {
+        for (name, count) in counts {
+            *totals.entry(name).or_default() += count;
+        }
+    }
+    let mut ranked: Vec<Ranked> = totals
+        .into_iter()
+        .map(|(name, count)| Ranked {
+            name: name.to_owned(),
+            count,
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    ranked.truncate(count.unwrap_or(DEFAULT_TOP_COUNT) as usize);
+    ranked
+}
+
+/// Average the percentages of each host weighted by its number of queries. If no host has any
+/// queries every host is weighted equally.
+fn weighted<'a>(hosts: impl Iterator<Item = (&'a HashMap<String, f64>, u64)>) -> Vec<Share> {
+    let hosts: Vec<(&HashMap<String, f64>, f64)> = hosts
+        .map(|(shares, weight)| (shares, weight as f64))
+        .collect();
+    let total: f64 = hosts.iter().map(|(_, weight)| weight).sum();
+
+    let mut combined: HashMap<String, f64> = HashMap::new();
+    for (shares, weight) in &hosts {
+        let weight = if total > 0.0 {
+            weight / total
+        } else {
+            1.0 / hosts.len() as f64
+        };
+        for (name, share) in *shares {
+            *combined.entry(name.clone()).or_default() += share * weight;
+        }
+    }
+    let mut combined: Vec<Share> = combined
+        .into_iter()
+        .map(|(name, percentage)| Share { name, percentage })
+        .collect();
+    combined.sort_by(|a, b| {
+        b.percentage
+            .total_cmp(&a.percentage)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    combined
+}
+
+impl Aggregate {
+    /// Rows in the same columns as the results of a single host, under a host of e.g.
+    /// "total of 3 hosts"
+    pub fn to_table_rows(&self) -> Vec<Vec<TableCell>> {
+        let host = match self.hosts.len() {
+            1 => "total of 1 host".to_owned(),
+            count => format!("total of {} hosts", count),
+        };
+        let ranked = |entries: &[Ranked]| -> Vec<Vec<TableCell>> {
+            entries
+                .iter()
+                .map(|entry| {
+                    vec![
+                        host.as_str().cell(),
+                        entry.name.as_str().cell(),
+                        entry.count.cell(),
+                    ]
+                })
+                .collect()
+        };
+        let shares = |entries: &[Share]| -> Vec<Vec<TableCell>> {
+            entries
+                .iter()
+                .map(|entry| {
+                    vec![
+                        host.as_str().cell(),
+                        entry.name.as_str().cell(),
+                        format!("{:.2}", entry.percentage).cell(),
+                    ]
+                })
+                .collect()
+        };
+        match &self.totals {
+            Totals::Summary(summary) => SummaryRaw {
+                status: summary.status.clone(),
+                ..*summary
+            }
+            .to_table_rows(&host),
+            Totals::TopItems {
+                top_queries,
+                top_ads,
+            } => {
+                let top_queries = top_queries.iter().map(|entry| {
+                    vec![
+                        host.as_str().cell(),
+                        "ok".cell().foreground_color(Some(Color::Green)),
+                        entry.name.as_str().cell(),
+                        entry.count.cell(),
+                    ]
+                });
+                let top_ads = top_ads.iter().map(|entry| {
+                    vec![
+                        host.as_str().cell(),
+                        "ad".cell().foreground_color(Some(Color::Red)),
+                        entry.name.as_str().cell(),
+                        entry.count.cell(),
+                    ]
+                });
+                top_queries.chain(top_ads).collect()
+            }
+            Totals::TopClients { top_sources } => ranked(top_sources),
+            Totals::TopClientsBlocked {
+                top_sources_blocked,
+            } => ranked(top_sources_blocked),
+            Totals::QueryTypes { querytypes } => shares(querytypes),
+            Totals::ForwardDestinations {
+                forward_destinations,
+            } => shares(forward_destinations),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(entries: &[(&str, u64)]) -> HashMap<String, u64> {
+        entries
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn top_lists_are_fetched_deeper_than_shown() {
+        assert_eq!(fetched(Some(1)), Some(3));
+        assert_eq!(fetched(None), Some(30));
+
+        // Second on each host, but first in total
+        let hosts = [
+            counts(&[("a.example", 10), ("c.example", 8)]),
+            counts(&[("b.example", 10), ("c.example", 8)]),
+        ];
+        let ranked: Vec<_> = rank(hosts.iter(), Some(1))
+            .into_iter()
+            .map(|entry| (entry.name, entry.count))
+            .collect();
+        assert_eq!(ranked, [("c.example".to_owned(), 16)]);
+    }
+}
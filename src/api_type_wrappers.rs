@@ -1,7 +1,8 @@
 // Wrappers for API responses which return a "generic" datatype e.g. u64 or HashMap<String, u64>
 // Allows traits such as ToTable to target the types correctly
-use crate::table::{ToTableRows, ToTableTitle};
-use cli_table::{Cell, CellStruct};
+use crate::table::{TableCell, ToTableCell, ToTableRows, ToTableTitle};
+use pi_hole_api::api_types::Query;
+use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 use std::collections::HashMap;
 
@@ -9,21 +10,21 @@ use std::collections::HashMap;
 pub struct OverTimeDataClientsWrapper(pub HashMap<String, Vec<u64>>);
 
 impl ToTableRows for OverTimeDataClientsWrapper {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.0
             .into_iter()
             .flat_map(|(time, counts)| {
                 counts
                     .into_iter()
                     .map(|count| vec![host.cell(), time.to_owned().cell(), count.cell()])
-                    .collect::<Vec<Vec<CellStruct>>>()
+                    .collect::<Vec<Vec<TableCell>>>()
             })
             .collect()
     }
 }
 
 impl ToTableTitle for OverTimeDataClientsWrapper {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "time".cell(), "count".cell()]
     }
 }
@@ -32,13 +33,13 @@ impl ToTableTitle for OverTimeDataClientsWrapper {
 pub struct QueriesCountWrapper(pub u64);
 
 impl ToTableRows for QueriesCountWrapper {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![host.cell(), self.0.cell()]]
     }
 }
 
 impl ToTableTitle for QueriesCountWrapper {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "count".cell()]
     }
 }
@@ -47,13 +48,13 @@ impl ToTableTitle for QueriesCountWrapper {
 pub struct VersionWrapper(pub u32);
 
 impl ToTableRows for VersionWrapper {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![host.cell(), self.0.cell()]]
     }
 }
 
 impl ToTableTitle for VersionWrapper {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "version".cell()]
     }
 }
@@ -62,13 +63,53 @@ impl ToTableTitle for VersionWrapper {
 pub struct LogageWrapper(pub f32);
 
 impl ToTableRows for LogageWrapper {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![host.cell(), self.0.cell()]]
     }
 }
 
 impl ToTableTitle for LogageWrapper {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "Logage".cell()]
     }
 }
+
+/// Serialises a query as an object with named fields, rather than the positional array of
+/// `Query`. Statuses are named as in the table and the response time is in milliseconds.
+pub struct QueryRecord<'a>(pub &'a Query);
+
+impl Serialize for QueryRecord<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let query = self.0;
+        let mut record = serializer.serialize_struct("Query", 12)?;
+        record.serialize_field("timestring", &query.timestring)?;
+        record.serialize_field("query_type", &format!("{:?}", query.query_type))?;
+        record.serialize_field("domain", &query.domain)?;
+        record.serialize_field("client", &query.client)?;
+        record.serialize_field("status", &format!("{:?}", query.status))?;
+        record.serialize_field("dnssec_status", &format!("{:?}", query.dnssec_status))?;
+        record.serialize_field("reply_type", &format!("{:?}", query.reply_type))?;
+        record.serialize_field(
+            "response_time_ms",
+            &(query.response_time.as_secs_f64() * 1000.0),
+        )?;
+        record.serialize_field("cname_domain", &query.cname_domain)?;
+        record.serialize_field("regex_id", &query.regex_id)?;
+        record.serialize_field("upstream_destination", &query.upstream_destination)?;
+        record.serialize_field("ede", &query.ede)?;
+        record.end()
+    }
+}
+
+/// Serialise queries with [`QueryRecord`]
+pub fn serialize_queries<S: Serializer>(
+    queries: &[Query],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(queries.iter().map(QueryRecord))
+}
+
+/// Serialise a single query with [`QueryRecord`]
+pub fn serialize_query<S: Serializer>(query: &Query, serializer: S) -> Result<S::Ok, S::Error> {
+    QueryRecord(query).serialize(serializer)
+}
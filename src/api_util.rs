@@ -1,55 +1,258 @@
 use crate::api_type_wrappers::*;
-use crate::table::ToTableRows;
-use cli_table::CellStruct;
+use crate::bulk::BulkListSummary;
+use crate::config::ApiKey;
+use crate::error::HostError;
+pub use crate::http::PiHoleConfigImplementation;
+use crate::list_test::ListTest;
+use crate::state::{AppliedChange, Change, SyncSummary};
+use crate::table::{TableCell, ToTableRows};
+use clap::ArgEnum;
 use pi_hole_api::{
-    api_types::*, errors::APIError, AuthenticatedPiHoleAPI, PiHoleAPIConfig,
-    PiHoleAPIConfigWithKey, UnauthenticatedPiHoleAPI,
+    api_types::*, errors::APIError, AuthenticatedPiHoleAPI, UnauthenticatedPiHoleAPI,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-pub enum PiHoleConfigImplementation {
-    Default(PiHoleAPIConfig),
-    WithKey(PiHoleAPIConfigWithKey),
+/// Source of the Pi-Hole API used by commands.
+/// `PiHoleConfigImplementation` talks to a real Pi-Hole over HTTP, while
+/// [`FakePiHole`](crate::fake::FakePiHole) keeps its state in memory.
+pub trait PiHoleBackend: Send + Sync {
+    fn get_unauthenticated_api(&self) -> &dyn UnauthenticatedPiHoleAPI;
+
+    /// Returns `APIError::MissingAPIKey` if the backend has no API key
+    fn get_authenticated_api(&self) -> Result<&dyn AuthenticatedPiHoleAPI, APIError>;
+}
+
+/// A list accepted by the list endpoints of the Pi-Hole API
+#[derive(
+    ArgEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ListName {
+    #[clap(name = "white")]
+    White,
+    #[clap(name = "black")]
+    Black,
+    #[clap(name = "white_regex")]
+    WhiteRegex,
+    #[clap(name = "black_regex")]
+    BlackRegex,
+    #[clap(name = "white_wild")]
+    WhiteWild,
+    #[clap(name = "black_wild")]
+    BlackWild,
+    #[clap(name = "audit")]
+    Audit,
 }
 
-impl From<PiHoleAPIConfig> for PiHoleConfigImplementation {
-    fn from(config: PiHoleAPIConfig) -> Self {
-        PiHoleConfigImplementation::Default(config)
+impl ListName {
+    /// Name of the list in the Pi-Hole API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::White => "white",
+            Self::Black => "black",
+            Self::WhiteRegex => "white_regex",
+            Self::BlackRegex => "black_regex",
+            Self::WhiteWild => "white_wild",
+            Self::BlackWild => "black_wild",
+            Self::Audit => "audit",
+        }
+    }
+
+    /// Regex lists hold regular expressions, the others hold domains
+    pub fn is_regex(&self) -> bool {
+        matches!(self, Self::WhiteRegex | Self::BlackRegex)
+    }
+
+    /// Wildcard lists take a domain, which the Pi-Hole stores as a regex
+    pub fn is_wild(&self) -> bool {
+        matches!(self, Self::WhiteWild | Self::BlackWild)
+    }
+
+    /// Lists which accept several domains separated by spaces in a single add. The regex and
+    /// wildcard lists treat the whole value as one entry.
+    pub fn is_batched(&self) -> bool {
+        matches!(self, Self::White | Self::Black)
+    }
+
+    /// Whitelists allow the domains they match
+    pub fn is_white(&self) -> bool {
+        matches!(self, Self::White | Self::WhiteRegex | Self::WhiteWild)
     }
+
+    /// List which holds the entries of this list on the Pi-Hole. Wildcard entries are stored in
+    /// the regex list, in the form given by [`wildcard_regex`].
+    pub fn stored_in(&self) -> ListName {
+        match self {
+            Self::WhiteWild => Self::WhiteRegex,
+            Self::BlackWild => Self::BlackRegex,
+            _ => *self,
+        }
+    }
+}
+
+/// Regex the Pi-Hole stores for a wildcard entry, e.g. `(\.|^)example\.com$` for `example.com`
+pub fn wildcard_regex(domain: &str) -> String {
+    format!("(\\.|^){}$", domain.replace('.', "\\."))
 }
 
-impl From<PiHoleAPIConfigWithKey> for PiHoleConfigImplementation {
-    fn from(config: PiHoleAPIConfigWithKey) -> Self {
-        PiHoleConfigImplementation::WithKey(config)
+/// Domain of a regex in the form given by [`wildcard_regex`], or `None` for any other regex
+pub fn wildcard_domain(regex: &str) -> Option<String> {
+    let domain = regex
+        .strip_prefix("(\\.|^)")?
+        .strip_suffix('$')?
+        .replace("\\.", ".");
+    let plain = domain
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    (plain && wildcard_regex(&domain) == regex).then_some(domain)
+}
+
+impl fmt::Display for ListName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-impl PiHoleConfigImplementation {
-    pub fn new(host: String, api_key: Option<String>) -> Self {
-        match api_key {
-            Some(key) => PiHoleAPIConfigWithKey::new(host, key).into(),
-            None => PiHoleAPIConfig::new(host).into(),
+/// Controls how calls to a single host are made
+#[derive(Debug, Clone, Default)]
+pub struct CallPolicy {
+    /// Abandon an attempt if the host has not responded within this time
+    pub timeout: Option<Duration>,
+    /// Number of extra attempts made after a connection failure or timeout
+    pub retries: u32,
+    /// Delay before the first retry, doubled after each further attempt
+    pub backoff: Duration,
+}
+
+/// A Pi-Hole instance together with the policy used when calling it
+pub struct PiHoleHost {
+    /// Address of the Pi-Hole e.g. http://localhost
+    pub host: String,
+    /// Name of the host in the config file, if it was selected through a group
+    pub name: Option<String>,
+    pub api: Arc<dyn PiHoleBackend>,
+    pub policy: CallPolicy,
+    /// Groups from the config file which selected the host, empty for hosts given with --hosts
+    pub groups: Vec<String>,
+    /// Kept so the host can be redirected to another address
+    key: Option<String>,
+}
+
+impl PiHoleHost {
+    /// Keys with a length of 10 or less are treated as no key
+    pub fn new(host: String, name: Option<String>, key: &ApiKey, policy: CallPolicy) -> Self {
+        let api_key = usable_key(key);
+        let api = Arc::new(PiHoleConfigImplementation::new(
+            host.clone(),
+            api_key.clone(),
+            policy.timeout,
+        ));
+        PiHoleHost {
+            key: api_key,
+            ..Self::with_backend(host, name, api, policy)
         }
     }
 
-    pub fn get_unauthenticated_api(&self) -> &dyn UnauthenticatedPiHoleAPI {
-        match self {
-            Self::Default(config) => config,
-            Self::WithKey(config) => config,
+    /// Use any backend for the host, such as a [`FakePiHole`](crate::fake::FakePiHole)
+    pub fn with_backend(
+        host: String,
+        name: Option<String>,
+        api: Arc<dyn PiHoleBackend>,
+        policy: CallPolicy,
+    ) -> Self {
+        PiHoleHost {
+            host,
+            name,
+            api,
+            policy,
+            groups: Vec::new(),
+            key: None,
         }
     }
 
-    pub fn get_authenticated_api(&self) -> Result<&dyn AuthenticatedPiHoleAPI, APIError> {
-        match self {
-            Self::Default(_) => Err(APIError::MissingAPIKey),
-            Self::WithKey(config) => Ok(config),
+    /// Send calls for this host to `address` over HTTP using the same API key.
+    /// The host is still reported under its original address.
+    pub fn redirect(&mut self, address: String) {
+        self.api = Arc::new(PiHoleConfigImplementation::new(
+            address,
+            self.key.clone(),
+            self.policy.timeout,
+        ));
+    }
+
+    /// As [`redirect`](Self::redirect), but authenticate at `address` with `key` instead
+    pub fn redirect_with_key(&mut self, address: String, key: &ApiKey) {
+        self.key = usable_key(key);
+        self.redirect(address);
+    }
+}
+
+/// The key to send to the host, if `key` is long enough to be one
+fn usable_key(key: &ApiKey) -> Option<String> {
+    if key.expose().len() > 10 {
+        Some(key.expose().to_owned())
+    } else {
+        None
+    }
+}
+
+/// Call the API for a host, applying the retry policy of that host. The timeout is applied by
+/// the HTTP client of the host, so an attempt which times out has stopped before the next begins.
+/// Only connection failures and timeouts are retried.
+pub fn call_with_policy<C: CallApi>(
+    command: &C,
+    host: &PiHoleHost,
+) -> Result<C::Output, HostError> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = command
+            .call(host.api.as_ref())
+            .map_err(|error| match host.policy.timeout {
+                Some(after) if timed_out(&error) => HostError::Timeout { after, attempts },
+                _ => HostError::from(error),
+            });
+
+        let retryable = matches!(
+            result,
+            Err(HostError::Transport(_)) | Err(HostError::Timeout { .. })
+        );
+        if !retryable || attempts > host.policy.retries {
+            return result;
+        }
+        thread::sleep(host.policy.backoff * 2u32.saturating_pow(attempts - 1));
+    }
+}
+
+/// Whether a request failed because the host did not respond in time, either while connecting
+/// or while reading the response
+fn timed_out(error: &APIError) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = match error {
+        APIError::RequestError(error) => Some(error),
+        APIError::IntoJsonError(error) => Some(error),
+        _ => None,
+    };
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            if error.kind() == std::io::ErrorKind::TimedOut {
+                return true;
+            }
         }
+        source = error.source();
     }
+    false
 }
 
 #[derive(Debug, Serialize)]
+#[serde(untagged)]
 pub enum APIResult {
-    AllQueries(Vec<Query>),
+    AllQueries(#[serde(serialize_with = "serialize_queries")] Vec<Query>),
     CacheInfo(CacheInfo),
     ClientNames(Vec<ClientName>),
     CustomCNAMERecords(Vec<CustomCNAMERecord>),
@@ -60,7 +263,7 @@ pub enum APIResult {
     Network(Network),
     NetworkClient(NetworkClient),
     OverTimeData(OverTimeData),
-    Query(Query),
+    Query(#[serde(serialize_with = "serialize_query")] Query),
     QueryTypes(QueryTypes),
     Status(Status),
     Summary(Summary),
@@ -74,10 +277,15 @@ pub enum APIResult {
     QueriesCountWrapper(QueriesCountWrapper),
     VersionWrapper(VersionWrapper),
     LogageWrapper(LogageWrapper),
+    Changes(Vec<Change>),
+    AppliedChanges(Vec<AppliedChange>),
+    BulkListSummary(BulkListSummary),
+    ListTest(ListTest),
+    SyncSummary(SyncSummary),
 }
 
 impl ToTableRows for APIResult {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         match self {
             Self::AllQueries(data) => data.to_table_rows(host),
             Self::CacheInfo(data) => data.to_table_rows(host),
@@ -104,6 +312,54 @@ impl ToTableRows for APIResult {
             Self::QueriesCountWrapper(data) => data.to_table_rows(host),
             Self::VersionWrapper(data) => data.to_table_rows(host),
             Self::LogageWrapper(data) => data.to_table_rows(host),
+            Self::Changes(data) => data.to_table_rows(host),
+            Self::AppliedChanges(data) => data.to_table_rows(host),
+            Self::BulkListSummary(data) => data.to_table_rows(host),
+            Self::ListTest(data) => data.to_table_rows(host),
+            Self::SyncSummary(data) => data.to_table_rows(host),
+        }
+    }
+
+    fn failure_lines(&self, host: &str) -> Vec<String> {
+        match self {
+            Self::BulkListSummary(data) => data.failure_lines(host),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl APIResult {
+    /// Whether a host which responded reported changes which it did not make, so that the run
+    /// is not a success
+    pub fn has_failures(&self) -> bool {
+        match self {
+            Self::BulkListSummary(summary) => summary.failed > 0,
+            Self::AppliedChanges(changes) => changes.iter().any(|applied| !applied.success),
+            Self::SyncSummary(summary) => summary.failed > 0,
+            _ => false,
+        }
+    }
+
+    /// Split list shaped results into one JSON value per entry.
+    /// Results which are not lists are returned unchanged as the error.
+    pub fn into_entries(self) -> Result<Vec<serde_json::Value>, Self> {
+        fn to_values<T: Serialize>(entries: Vec<T>) -> Vec<serde_json::Value> {
+            entries
+                .iter()
+                .map(|entry| serde_json::to_value(entry).expect("Unable to serialise entry"))
+                .collect()
+        }
+
+        match self {
+            Self::AllQueries(data) => Ok(to_values(data.iter().map(QueryRecord).collect())),
+            Self::ClientNames(data) => Ok(to_values(data)),
+            Self::CustomCNAMERecords(data) => Ok(to_values(data)),
+            Self::CustomDNSRecords(data) => Ok(to_values(data)),
+            Self::CustomListDomainDetailsList(data) => Ok(to_values(data)),
+            Self::Network(data) => Ok(to_values(data.network)),
+            Self::Changes(data) => Ok(to_values(data)),
+            Self::AppliedChanges(data) => Ok(to_values(data)),
+            other => Err(other),
         }
     }
 }
@@ -114,6 +370,12 @@ impl ToTableRows for APIResult {
 //     }
 // }
 
+impl From<ListTest> for APIResult {
+    fn from(data: ListTest) -> Self {
+        APIResult::ListTest(data)
+    }
+}
+
 impl From<Vec<Query>> for APIResult {
     fn from(data: Vec<Query>) -> Self {
         APIResult::AllQueries(data)
@@ -241,6 +503,239 @@ impl From<LogageWrapper> for APIResult {
     }
 }
 
+impl From<BulkListSummary> for APIResult {
+    fn from(data: BulkListSummary) -> Self {
+        APIResult::BulkListSummary(data)
+    }
+}
+
+impl From<SyncSummary> for APIResult {
+    fn from(data: SyncSummary) -> Self {
+        APIResult::SyncSummary(data)
+    }
+}
+
+/// A request to a single Pi-Hole. Commands which are displayed return an [`APIResult`], while
+/// those whose result is used by another step return its type.
 pub trait CallApi {
-    fn call(&self, api: &PiHoleConfigImplementation) -> Result<APIResult, APIError>;
+    type Output;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<Self::Output, APIError>;
+}
+
+/// A single call of the Pi-Hole API, for callers which need its response rather than an
+/// [`APIResult`]
+pub struct ApiCall<F>(pub F);
+
+impl<F, T> CallApi for ApiCall<F>
+where
+    F: Fn(&dyn PiHoleBackend) -> Result<T, APIError>,
+{
+    type Output = T;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<T, APIError> {
+        (self.0)(api)
+    }
+}
+
+/// Run `call` against every host, with at most `parallelism` calls in flight at once.
+/// `on_result` is invoked on the calling thread with the index of the host as each call completes.
+/// No further hosts are called once `stop` returns true for a result, although calls which are
+/// already in flight still complete.
+pub fn call_each<T, F, R, S, C>(apis: &[T], parallelism: usize, call: F, stop: S, mut on_result: C)
+where
+    T: Sync,
+    F: Fn(&T) -> R + Sync,
+    R: Send,
+    S: Fn(&R) -> bool + Sync,
+    C: FnMut(usize, R),
+{
+    let workers = parallelism.clamp(1, apis.len().max(1));
+    let next_index = AtomicUsize::new(0);
+    let stopped = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let next_index = &next_index;
+            let stopped = &stopped;
+            let call = &call;
+            let stop = &stop;
+            scope.spawn(move || loop {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let index = next_index.fetch_add(1, Ordering::SeqCst);
+                if index >= apis.len() {
+                    break;
+                }
+                let result = call(&apis[index]);
+                if stop(&result) {
+                    stopped.store(true, Ordering::SeqCst);
+                }
+                if sender.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Drop the original sender so the receiver finishes once every worker is done
+        drop(sender);
+
+        for (index, result) in receiver {
+            on_result(index, result);
+        }
+    });
+}
+
+/// Run `call` against every host concurrently and return the results in host order.
+/// No further hosts are called once `stop` returns true for a result, and hosts which were
+/// never called have no result.
+pub fn call_all_until<T, F, R, S>(
+    apis: &[T],
+    parallelism: usize,
+    call: F,
+    stop: S,
+) -> Vec<Option<R>>
+where
+    T: Sync,
+    F: Fn(&T) -> R + Sync,
+    R: Send,
+    S: Fn(&R) -> bool + Sync,
+{
+    let mut results: Vec<Option<R>> = apis.iter().map(|_| None).collect();
+    call_each(apis, parallelism, call, stop, |index, result| {
+        results[index] = Some(result)
+    });
+    results
+}
+
+/// Call `command` against every host using each host's call policy and return the results in
+/// host order. With `fail_fast`, hosts which were not called before the first failure are
+/// reported as skipped.
+pub fn call_hosts<C>(
+    command: &C,
+    hosts: &[PiHoleHost],
+    parallelism: usize,
+    fail_fast: bool,
+) -> Vec<Result<C::Output, HostError>>
+where
+    C: CallApi + Sync,
+    C::Output: Send,
+{
+    call_all_until(
+        hosts,
+        parallelism,
+        |host| call_with_policy(command, host),
+        |result| fail_fast && result.is_err(),
+    )
+    .into_iter()
+    .map(|result| result.unwrap_or(Err(HostError::Skipped)))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{ApiCommands, ListCommands};
+    use crate::fake::FakePiHole;
+    use std::time::Instant;
+
+    fn host(name: &str, fake: FakePiHole) -> PiHoleHost {
+        PiHoleHost::with_backend(
+            format!("http://{}", name),
+            None,
+            Arc::new(fake),
+            CallPolicy::default(),
+        )
+    }
+
+    fn show_black() -> ApiCommands {
+        ApiCommands::List {
+            list: Some(ListName::Black),
+            command: ListCommands::Show,
+        }
+    }
+
+    fn domains(result: &Result<APIResult, HostError>) -> Vec<&str> {
+        match result {
+            Ok(APIResult::CustomListDomainDetailsList(domains)) => {
+                domains.iter().map(|d| d.domain.as_str()).collect()
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn results_are_in_host_order() {
+        let hosts = vec![
+            host(
+                "slow",
+                FakePiHole::new()
+                    .with_delay(Duration::from_millis(200))
+                    .with_list_domain(ListName::Black, "slow.example"),
+            ),
+            host(
+                "fast",
+                FakePiHole::new().with_list_domain(ListName::Black, "fast.example"),
+            ),
+        ];
+        let results = call_hosts(&show_black(), &hosts, 2, false);
+        assert_eq!(domains(&results[0]), ["slow.example"]);
+        assert_eq!(domains(&results[1]), ["fast.example"]);
+    }
+
+    #[test]
+    fn failures_are_reported_for_their_host_only() {
+        let hosts = vec![
+            host("first", FakePiHole::new()),
+            host(
+                "failing",
+                FakePiHole::new().failing_with(|| APIError::FTLNotRunning),
+            ),
+            host("keyless", FakePiHole::new().without_key()),
+        ];
+        let results = call_hosts(&show_black(), &hosts, 3, false);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(HostError::Api(APIError::FTLNotRunning))
+        ));
+        assert!(matches!(
+            results[2],
+            Err(HostError::Api(APIError::MissingAPIKey))
+        ));
+    }
+
+    #[test]
+    fn fail_fast_skips_the_remaining_hosts() {
+        let hosts = vec![
+            host(
+                "failing",
+                FakePiHole::new().failing_with(|| APIError::FTLNotRunning),
+            ),
+            host("second", FakePiHole::new()),
+            host("third", FakePiHole::new()),
+        ];
+        let results = call_hosts(&show_black(), &hosts, 1, true);
+        assert!(matches!(results[0], Err(HostError::Api(_))));
+        assert!(matches!(results[1], Err(HostError::Skipped)));
+        assert!(matches!(results[2], Err(HostError::Skipped)));
+    }
+
+    #[test]
+    fn api_errors_are_not_retried() {
+        let mut failing = host(
+            "failing",
+            FakePiHole::new().failing_with(|| APIError::FTLNotRunning),
+        );
+        failing.policy = CallPolicy {
+            timeout: None,
+            retries: 3,
+            backoff: Duration::from_secs(5),
+        };
+        let started = Instant::now();
+        assert!(call_with_policy(&show_black(), &failing).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
 }
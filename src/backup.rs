@@ -0,0 +1,281 @@
+use crate::api_util::{wildcard_domain, CallApi, ListName, PiHoleBackend};
+use crate::error::Error;
+use crate::state::{CnameRecord, DesiredState, DnsRecord};
+use crate::validate;
+use pi_hole_api::errors::APIError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::path::Path;
+
+/// Managed configuration of a single Pi-Hole, as written by `export` and read by `import`.
+/// Entries are sorted so that successive exports of an unchanged host are identical.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Backup {
+    /// Address of the host which was exported
+    pub host: String,
+    /// Maximum age in hours of the queries in the log
+    pub max_logage: f32,
+    pub dns: BTreeSet<DnsRecord>,
+    pub cname: BTreeSet<CnameRecord>,
+    /// Domains of each list, keyed by list name e.g. black or white_regex
+    pub lists: BTreeMap<ListName, Vec<BackupDomain>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackupDomain {
+    pub domain: String,
+    pub enabled: bool,
+    pub comment: String,
+}
+
+impl Backup {
+    /// Read everything which `export` captures. The audit log is not a blocking list, so it is
+    /// left out as it is by `sync`. Wildcard entries are captured in the regex lists which hold
+    /// them.
+    pub fn read(api: &dyn PiHoleBackend) -> Result<Backup, APIError> {
+        let records = DesiredState {
+            dns: Some(BTreeSet::new()),
+            cname: Some(BTreeSet::new()),
+            ..Default::default()
+        }
+        .current(api)?;
+        let api = api.get_authenticated_api()?;
+
+        let mut lists = BTreeMap::new();
+        for list in DesiredState::all_sections().lists.keys() {
+            let mut domains: Vec<BackupDomain> = api
+                .list_get_domains(list.as_str())?
+                .into_iter()
+                .map(|details| BackupDomain {
+                    domain: details.domain,
+                    enabled: details.enabled,
+                    comment: details.comment,
+                })
+                .collect();
+            domains.sort_by(|a, b| a.domain.cmp(&b.domain));
+            lists.insert(*list, domains);
+        }
+
+        Ok(Backup {
+            host: String::new(),
+            max_logage: api.get_max_logage()?,
+            dns: records.dns.unwrap_or_default(),
+            cname: records.cname.unwrap_or_default(),
+            lists,
+        })
+    }
+
+    /// Entries which `import` restores. Every section is managed, but nothing is removed as
+    /// `import` only adds the missing entries. Disabled entries are left out, as the API can only
+    /// add enabled ones.
+    pub fn desired_state(&self) -> DesiredState {
+        let mut desired = DesiredState {
+            lists: self
+                .lists
+                .iter()
+                .map(|(list, domains)| {
+                    let domains = domains
+                        .iter()
+                        .filter(|entry| entry.enabled)
+                        .map(|entry| entry.domain.clone())
+                        .collect();
+                    (*list, domains)
+                })
+                .collect(),
+            dns: Some(self.dns.clone()),
+            cname: Some(self.cname.clone()),
+        };
+        desired.fold_wildcards();
+        desired
+    }
+
+    /// Entries which are disabled on the exported host, as (list, entry) pairs
+    pub fn disabled(&self) -> impl Iterator<Item = (ListName, &BackupDomain)> {
+        self.lists.iter().flat_map(|(list, domains)| {
+            domains
+                .iter()
+                .filter(|entry| !entry.enabled)
+                .map(move |entry| (*list, entry))
+        })
+    }
+}
+
+/// Read a file written by `export`
+pub fn read_backup_file(path: &Path) -> Result<Backup, Error> {
+    let file = File::open(path).map_err(|source| Error::BackupRead {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mut backup: Backup =
+        serde_json::from_reader(file).map_err(|source| Error::BackupParse {
+            path: path.to_owned(),
+            source,
+        })?;
+
+    let invalid = |message: String| Error::BackupInvalid {
+        path: path.to_owned(),
+        message,
+    };
+    for (list, domains) in backup.lists.iter_mut() {
+        for entry in domains.iter_mut() {
+            // Wildcard lists may hold the regex form the Pi-Hole returns
+            let domain = match list.is_wild() {
+                true => wildcard_domain(&entry.domain).unwrap_or_else(|| entry.domain.clone()),
+                false => entry.domain.clone(),
+            };
+            entry.domain = validate::list_entry(*list, &domain)
+                .map_err(|error| invalid(format!("invalid {} entry: {}", list, error)))?;
+        }
+    }
+    let records = |error: Error| invalid(format!("invalid record: {}", error));
+    backup.dns = backup
+        .dns
+        .iter()
+        .map(DnsRecord::validated)
+        .collect::<Result<_, _>>()
+        .map_err(records)?;
+    backup.cname = backup
+        .cname
+        .iter()
+        .map(CnameRecord::validated)
+        .collect::<Result<_, _>>()
+        .map_err(records)?;
+    Ok(backup)
+}
+
+/// Reads the managed configuration of a host
+pub struct ExportCommand;
+
+impl CallApi for ExportCommand {
+    type Output = Backup;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<Backup, APIError> {
+        Backup::read(api)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::FakePiHole;
+    use crate::state::SyncCommand;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn read(json: &str) -> Result<Backup, Error> {
+        // A shared path would let parallel tests overwrite each other's backup
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "piholectl-backup-{}-{}.json",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, json).unwrap();
+        let backup = read_backup_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        backup
+    }
+
+    fn backup(lists: &str) -> String {
+        format!(
+            r#"{{"host": "", "max_logage": 24.0, "dns": [], "cname": [], "lists": {}}}"#,
+            lists
+        )
+    }
+
+    #[test]
+    fn export_and_import_keep_wildcards() {
+        let source = FakePiHole::new().with_list_domain(ListName::Black, "ads.example.net");
+        source
+            .get_authenticated_api()
+            .unwrap()
+            .list_add("example.com", "white_wild")
+            .unwrap();
+        let backup = Backup::read(&source).unwrap();
+        assert!(!backup.lists.contains_key(&ListName::WhiteWild));
+        let regexes: Vec<_> = backup.lists[&ListName::WhiteRegex]
+            .iter()
+            .map(|entry| entry.domain.as_str())
+            .collect();
+        assert_eq!(regexes, [r"(\.|^)example\.com$"]);
+
+        let replica = FakePiHole::new();
+        let import = SyncCommand {
+            source: backup.desired_state(),
+            prune: false,
+        };
+        import.call(&replica).unwrap();
+        assert_eq!(replica.list_domains(ListName::Black), ["ads.example.net"]);
+        assert_eq!(
+            replica.list_domains(ListName::WhiteRegex),
+            [r"(\.|^)example\.com$"]
+        );
+    }
+
+    #[test]
+    fn wildcard_lists_are_restored_once() {
+        let backup = read(&backup(
+            r#"{
+                "black_wild": [
+                    {"domain": "(\\.|^)tracker\\.example\\.org$", "enabled": true, "comment": ""},
+                    {"domain": "ads.example.com", "enabled": true, "comment": ""}
+                ],
+                "black_regex": [
+                    {"domain": "(\\.|^)tracker\\.example\\.org$", "enabled": true, "comment": ""}
+                ]
+            }"#,
+        ))
+        .unwrap();
+        let desired = backup.desired_state();
+        assert_eq!(
+            desired.lists.keys().collect::<Vec<_>>(),
+            [&ListName::BlackRegex]
+        );
+        assert_eq!(
+            desired.lists[&ListName::BlackRegex]
+                .iter()
+                .collect::<Vec<_>>(),
+            [r"(\.|^)ads\.example\.com$", r"(\.|^)tracker\.example\.org$"]
+        );
+    }
+
+    #[test]
+    fn disabled_entries_are_not_restored() {
+        let backup = read(&backup(
+            r#"{"black": [
+                {"domain": "on.example", "enabled": true, "comment": ""},
+                {"domain": "off.example", "enabled": false, "comment": ""}
+            ]}"#,
+        ))
+        .unwrap();
+        assert_eq!(
+            backup.desired_state().lists[&ListName::Black]
+                .iter()
+                .collect::<Vec<_>>(),
+            ["on.example"]
+        );
+        let disabled: Vec<_> = backup
+            .disabled()
+            .map(|(list, entry)| (list, entry.domain.as_str()))
+            .collect();
+        assert_eq!(disabled, [(ListName::Black, "off.example")]);
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let unknown = read(&backup(r#"{"grey": []}"#));
+        assert!(matches!(unknown, Err(Error::BackupParse { .. })));
+
+        let domain = read(&backup(
+            r#"{"white": [{"domain": "not a domain", "enabled": true, "comment": ""}]}"#,
+        ));
+        match domain {
+            Err(Error::BackupInvalid { message, .. }) => {
+                assert!(message.starts_with("invalid white entry: "), "{}", message)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use clap::Parser;
+use piholectl::mock_server::{MockServer, BUNDLED_FIXTURES};
+use std::path::PathBuf;
+
+/// Mock Pi-Hole which serves the API from fixture files, for testing piholectl without a Pi-Hole
+#[derive(Parser, Debug)]
+#[clap(author, version, about)]
+struct MockOptions {
+    /// Address to listen on. Port 0 picks a free port
+    #[clap(short, long, default_value = "127.0.0.1:0")]
+    address: String,
+
+    /// Directory of fixture files. Defaults to the fixtures bundled with piholectl
+    #[clap(short, long)]
+    fixtures: Option<PathBuf>,
+
+    /// API key expected in the auth parameter. If not set any key is accepted
+    #[clap(short = 'k', long)]
+    api_key: Option<String>,
+}
+
+fn main() {
+    let opts = MockOptions::parse();
+    let fixtures = opts
+        .fixtures
+        .unwrap_or_else(|| PathBuf::from(BUNDLED_FIXTURES));
+
+    let server = match MockServer::bind(&opts.address, fixtures, opts.api_key) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Error: unable to listen on {}: {}", opts.address, e);
+            std::process::exit(2);
+        }
+    };
+
+    // The address is the only line on stdout so that scripts can read it
+    println!("{}", server.url().expect("Listener has no local address"));
+    server.serve();
+}
@@ -0,0 +1,291 @@
+use crate::api_util::{wildcard_regex, CallApi, ListName, PiHoleBackend};
+use crate::error::{Error, HostError};
+use crate::state::Action;
+use crate::table::{TableCell, ToTableCell, ToTableRows};
+use cli_table::Color;
+use pi_hole_api::api_types::ListModificationResponse;
+use pi_hole_api::errors::APIError;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::slice;
+
+/// Number of domains sent in each add to a list which accepts several at once.
+/// Keeps the request URL well within the limits of the Pi-Hole web server.
+const BATCH_SIZE: usize = 100;
+
+/// Read one domain per line from a file, or from stdin if the path is `-`.
+/// Blank lines and lines starting with `#` are ignored.
+pub fn read_domains(path: &Path) -> Result<Vec<String>, Error> {
+    let reader: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).map_err(|source| Error::DomainsRead {
+            path: path.to_owned(),
+            source,
+        })?)
+    };
+
+    let mut domains = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|source| Error::DomainsRead {
+            path: path.to_owned(),
+            source,
+        })?;
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            domains.push(line.to_owned());
+        }
+    }
+    Ok(domains)
+}
+
+/// Adds or removes many domains on a list, skipping those which need no change.
+///
+/// Adds to the exact lists are sent in batches. The Pi-Hole API removes a single domain per
+/// request, so removals are only sent for domains which are on the list. The list is read again
+/// afterwards, so the counts reflect what the host holds rather than what it responded, while
+/// the response explains why a domain failed.
+pub struct BulkListCommand {
+    pub list: ListName,
+    pub action: Action,
+    pub domains: Vec<String>,
+}
+
+impl BulkListCommand {
+    pub fn to_table_title(&self) -> Vec<TableCell> {
+        let (changed, unchanged) = match self.action {
+            Action::Add => ("added", "already present"),
+            Action::Remove => ("removed", "not present"),
+        };
+        vec![
+            "Host".cell(),
+            changed.cell(),
+            unchanged.cell(),
+            "failed".cell(),
+        ]
+    }
+
+    fn read_list(&self, api: &dyn PiHoleBackend) -> Result<HashSet<String>, APIError> {
+        Ok(api
+            .get_authenticated_api()?
+            .list_get_domains(self.list.stored_in().as_str())?
+            .into_iter()
+            .map(|details| details.domain)
+            .collect())
+    }
+
+    /// Form in which the Pi-Hole stores `domain` on the list
+    fn stored_form(&self, domain: &str) -> String {
+        match self.list.is_wild() {
+            true => wildcard_regex(domain),
+            false => domain.to_owned(),
+        }
+    }
+}
+
+impl CallApi for BulkListCommand {
+    type Output = BulkListSummary;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<BulkListSummary, APIError> {
+        let before = self.read_list(api)?;
+        let mut requested = HashSet::new();
+        let domains: Vec<(&String, String)> = self
+            .domains
+            .iter()
+            .filter(|domain| requested.insert(*domain))
+            .map(|domain| (domain, self.stored_form(domain)))
+            .collect();
+        let (pending, unchanged): (Vec<_>, Vec<_>) = domains
+            .into_iter()
+            .partition(|(_, stored)| before.contains(stored) == (self.action == Action::Remove));
+
+        // Wildcard entries are sent in their stored form to the regex list which holds them
+        let list = self.list.stored_in();
+        let authenticated = api.get_authenticated_api()?;
+        let mut errors: HashMap<String, String> = HashMap::new();
+        let mut record = |domains: &[(&String, String)], response| {
+            let message = match response {
+                Ok(ListModificationResponse { success: true, .. }) => return,
+                Ok(ListModificationResponse { message, .. }) => {
+                    message.unwrap_or_else(|| "the host did not accept the change".to_owned())
+                }
+                Err(error) => HostError::from(error).message(),
+            };
+            for (domain, _) in domains {
+                errors.insert(domain.to_string(), message.clone());
+            }
+        };
+        match self.action {
+            Action::Add if list.is_batched() => {
+                for batch in pending.chunks(BATCH_SIZE) {
+                    let entries: Vec<&str> =
+                        batch.iter().map(|(_, stored)| stored.as_str()).collect();
+                    record(
+                        batch,
+                        authenticated.list_add(&entries.join(" "), list.as_str()),
+                    );
+                }
+            }
+            Action::Add => {
+                for entry in &pending {
+                    let response = authenticated.list_add(&entry.1, list.as_str());
+                    record(slice::from_ref(entry), response);
+                }
+            }
+            Action::Remove => {
+                for entry in &pending {
+                    let response = authenticated.list_remove(&entry.1, list.as_str());
+                    record(slice::from_ref(entry), response);
+                }
+            }
+        }
+
+        // The list is read again as a batch may be partly applied, or an error response may
+        // follow a change which was made
+        let after = self.read_list(api)?;
+        let failed: Vec<FailedDomain> = pending
+            .iter()
+            .filter(|(_, stored)| after.contains(stored) == (self.action == Action::Remove))
+            .map(|(domain, _)| FailedDomain {
+                domain: domain.to_string(),
+                message: errors.remove(domain.as_str()).unwrap_or_else(|| {
+                    match self.action {
+                        Action::Add => "not on the list after adding",
+                        Action::Remove => "still on the list after removing",
+                    }
+                    .to_owned()
+                }),
+            })
+            .collect();
+        Ok(BulkListSummary {
+            action: self.action,
+            changed: pending.len() - failed.len(),
+            unchanged: unchanged.len(),
+            failed: failed.len(),
+            failed_domains: failed,
+        })
+    }
+}
+
+/// Outcome of a bulk add or remove on a single host
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkListSummary {
+    pub action: Action,
+    /// Domains which were added or removed
+    pub changed: usize,
+    /// Domains which were already present when adding, or not present when removing
+    pub unchanged: usize,
+    pub failed: usize,
+    pub failed_domains: Vec<FailedDomain>,
+}
+
+/// A domain which was not added or removed, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedDomain {
+    pub domain: String,
+    pub message: String,
+}
+
+impl ToTableRows for BulkListSummary {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
+        let failed_color = if self.failed > 0 {
+            Some(Color::Red)
+        } else {
+            None
+        };
+        vec![vec![
+            host.cell(),
+            self.changed.cell(),
+            self.unchanged.cell(),
+            self.failed.cell().foreground_color(failed_color),
+        ]]
+    }
+
+    fn failure_lines(&self, host: &str) -> Vec<String> {
+        self.failed_domains
+            .iter()
+            .map(|failed| format!("{}: {}: {}", host, failed.domain, failed.message))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_util::APIResult;
+    use crate::fake::FakePiHole;
+
+    fn bulk(list: ListName, action: Action, domains: &[&str]) -> BulkListCommand {
+        BulkListCommand {
+            list,
+            action,
+            domains: domains.iter().map(|domain| domain.to_string()).collect(),
+        }
+    }
+
+    fn summary(command: &BulkListCommand, fake: &FakePiHole) -> BulkListSummary {
+        command.call(fake).unwrap()
+    }
+
+    #[test]
+    fn rejected_domains_are_reported_with_the_response() {
+        let fake = FakePiHole::new()
+            .with_list_domain(ListName::White, "present.example")
+            .rejecting("bad.example");
+        let command = bulk(
+            ListName::White,
+            Action::Add,
+            &["present.example", "new.example", "bad.example"],
+        );
+        let result = summary(&command, &fake);
+        assert_eq!((result.changed, result.unchanged, result.failed), (0, 1, 2));
+        // The rejected batch held both domains, so both carry the message
+        let failed: Vec<_> = result
+            .failed_domains
+            .iter()
+            .map(|failed| (failed.domain.as_str(), failed.message.as_str()))
+            .collect();
+        assert_eq!(
+            failed,
+            [
+                ("new.example", "bad.example is not a valid domain"),
+                ("bad.example", "bad.example is not a valid domain"),
+            ]
+        );
+        assert!(APIResult::BulkListSummary(result).has_failures());
+    }
+
+    #[test]
+    fn failing_hosts_are_errors() {
+        let fake = FakePiHole::new().failing_with(|| APIError::FTLNotRunning);
+        let command = bulk(ListName::Black, Action::Add, &["ads.example"]);
+        assert!(matches!(command.call(&fake), Err(APIError::FTLNotRunning)));
+    }
+
+    #[test]
+    fn wildcards_are_compared_in_stored_form() {
+        let fake = FakePiHole::new();
+        let add = bulk(
+            ListName::BlackWild,
+            Action::Add,
+            &["ads.example", "ads.example"],
+        );
+        let first = summary(&add, &fake);
+        assert_eq!((first.changed, first.unchanged, first.failed), (1, 0, 0));
+        assert_eq!(
+            fake.list_domains(ListName::BlackRegex),
+            [r"(\.|^)ads\.example$"]
+        );
+
+        let again = summary(&add, &fake);
+        assert_eq!((again.changed, again.unchanged, again.failed), (0, 1, 0));
+        assert!(!APIResult::BulkListSummary(again).has_failures());
+
+        let remove = bulk(ListName::BlackWild, Action::Remove, &["ads.example"]);
+        assert_eq!(summary(&remove, &fake).changed, 1);
+        assert!(fake.list_domains(ListName::BlackRegex).is_empty());
+    }
+}
@@ -1,8 +1,14 @@
 use crate::api_type_wrappers::*;
-use crate::api_util::{APIResult, CallApi, PiHoleConfigImplementation};
-use crate::table::{ToTableTitle, ToTableTitleDynamic};
+use crate::api_util::{APIResult, CallApi, ListName, PiHoleBackend};
+use crate::config::ApiKey;
+use crate::diff::DiffTarget;
+use crate::error::Error;
+use crate::list_test::{self, ListTest, TESTED_LISTS};
+use crate::output::OutputFormat;
+use crate::query_filter::QueryFilter;
+use crate::table::{TableCell, ToTableTitle, ToTableTitleDynamic};
+use crate::validate;
 use clap::{Parser, Subcommand};
-use cli_table::CellStruct;
 use pi_hole_api::api_types::*;
 use pi_hole_api::errors::APIError;
 use std::net::IpAddr;
@@ -19,9 +25,9 @@ pub struct PiHoleCtlOptions {
     #[clap(short, long)]
     pub verbose: bool,
 
-    /// Output as JSON
-    #[clap(short, long)]
-    pub json: bool,
+    /// Output format
+    #[clap(short, long, arg_enum, default_value = "table")]
+    pub output: OutputFormat,
 
     /// Hosts to manage
     #[clap(long)]
@@ -29,7 +35,7 @@ pub struct PiHoleCtlOptions {
 
     /// API key for a pihole instance. Anything with a length < 10 is considered no key.
     #[clap(long)]
-    pub keys: Vec<String>,
+    pub keys: Vec<ApiKey>,
 
     /// Path to config file
     #[clap(short, long)]
@@ -39,20 +45,100 @@ pub struct PiHoleCtlOptions {
     #[clap(short, long)]
     pub groups: Vec<String>,
 
+    /// Maximum number of hosts to query at the same time
+    #[clap(short, long, default_value_t = 8)]
+    pub parallelism: usize,
+
+    /// Give up on a host if it does not respond within this time e.g. 5s, 1m
+    #[clap(long, parse(try_from_str = parse_duration))]
+    pub timeout: Option<Duration>,
+
+    /// Number of times to retry a host after a connection failure or timeout
+    #[clap(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// Delay before retrying a host, doubled after each further attempt
+    #[clap(long, parse(try_from_str = parse_duration), default_value = "1s")]
+    pub backoff: Duration,
+
+    /// Stop calling further hosts as soon as one host fails
+    #[clap(long)]
+    pub fail_fast: bool,
+
+    /// Save the raw response from every host to this directory, with API keys removed
+    #[clap(long, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Serve the responses saved by --record instead of calling the hosts
+    #[clap(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Combine the results of every host into one table. Works with summary, summary-raw,
+    /// top-items, top-clients, top-clients-blocked, query-types and forward-destinations
+    #[clap(long)]
+    pub aggregate: bool,
+
+    /// Run the command again at this interval e.g. 5s, redrawing the table with the change in
+    /// each number since the previous refresh
+    #[clap(long, parse(try_from_str = parse_duration))]
+    pub watch: Option<Duration>,
+
     #[clap(subcommand)]
-    pub command: ApiCommands,
+    pub command: Commands,
 }
 
-// #[derive(Debug, Subcommand)]
-// pub enum Commands {
-//     /// Perform API request
-//     API {
-//         #[clap(subcommand)]
-//         command: ApiCommands,
-//     },
-// }
-
 #[derive(Debug, Subcommand)]
+pub enum Commands {
+    #[clap(flatten)]
+    Api(ApiCommands),
+    /// Show the changes needed for each host to match a state file
+    Plan {
+        /// YAML file declaring the lists, DNS and CNAME records of each group
+        #[clap(short, long)]
+        file: PathBuf,
+    },
+    /// Make the changes needed for each host to match a state file
+    Apply {
+        /// YAML file declaring the lists, DNS and CNAME records of each group
+        #[clap(short, long)]
+        file: PathBuf,
+    },
+    /// Show entries which are missing or different on some of the selected hosts
+    Diff {
+        #[clap(subcommand)]
+        target: DiffTarget,
+    },
+    /// Write the lists, DNS and CNAME records and max logage of a single host as JSON
+    Export,
+    /// Add the entries of a file written by export which are missing on each host
+    Import {
+        /// JSON file written by export
+        file: PathBuf,
+    },
+    /// Interactive view of the selected hosts, with keys to enable and disable blocking
+    Dashboard {
+        /// Delay between refreshes
+        #[clap(long, parse(try_from_str = parse_duration), default_value = "5s")]
+        interval: Duration,
+        /// Number of top blocked domains and clients to fetch from each host
+        #[clap(long, default_value_t = 10)]
+        count: u32,
+    },
+    /// Copy the lists, DNS and CNAME records of one Pi-Hole to the hosts of groups
+    Sync {
+        /// Host to copy from, either its name in the config file or one of --hosts
+        #[clap(long)]
+        from: String,
+        /// Groups to copy to. Hosts given with --hosts are also copied to
+        #[clap(long, required = true)]
+        to: Vec<String>,
+        /// Also remove entries which the source does not have
+        #[clap(long)]
+        prune: bool,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
 pub enum ApiCommands {
     /// Enable ad blocking
     Enable,
@@ -97,9 +183,21 @@ pub enum ApiCommands {
     QueryTypes,
     /// DNS query data
     AllQueries {
-        /// Limit number of results
+        /// Limit number of results. Filters are applied to the most recent queries of each host
+        /// up to this limit
         #[clap(default_value_t = 100)]
         count: u32,
+
+        #[clap(flatten)]
+        filter: QueryFilter,
+
+        /// Keep polling the hosts and write queries as they arrive, like pihole -t
+        #[clap(long)]
+        follow: bool,
+
+        /// Delay between polls when following
+        #[clap(long, parse(try_from_str = parse_duration), default_value = "2s", requires = "follow")]
+        interval: Duration,
     },
     /// Cache statistics
     Cache,
@@ -111,11 +209,12 @@ pub enum ApiCommands {
     Network,
     /// Total number of queries
     QueriesCount,
-    /// Show/Modify a black/whitelist
+    /// Show/Modify a black/whitelist, or test which entries match a domain
     List {
-        /// List to use
-        /// Acceptable lists are: `white`, `black`, `white_regex`, `black_regex`, `white_wild`, `black_wild`, `audit`.
-        list: String,
+        /// List to use. Optional for test, which checks the whitelist, regex and wildcard lists by
+        /// default
+        #[clap(arg_enum)]
+        list: Option<ListName>,
 
         #[clap(subcommand)]
         command: ListCommands,
@@ -134,8 +233,106 @@ pub enum ApiCommands {
     Logage,
 }
 
+impl ApiCommands {
+    /// Check the domains and regexes of the command before it is sent to any host, converting
+    /// internationalised domains to punycode
+    pub fn validated(self) -> Result<Self, Error> {
+        Ok(match self {
+            Self::List {
+                list,
+                command: ListCommands::Test { domain, query_type },
+            } => {
+                if list == Some(ListName::Audit) {
+                    return Err(Error::InvalidListCommand {
+                        message: "the audit log does not block or allow domains, test another list"
+                            .to_owned(),
+                    });
+                }
+                Self::List {
+                    list,
+                    command: ListCommands::Test {
+                        domain: validate::domain(&domain)?,
+                        query_type: query_type.to_ascii_uppercase(),
+                    },
+                }
+            }
+            Self::List { list: None, .. } => {
+                return Err(Error::InvalidListCommand {
+                    message: "a list is required, e.g. piholectl list black show".to_owned(),
+                })
+            }
+            Self::List {
+                list: Some(list),
+                command,
+            } => Self::List {
+                list: Some(list),
+                command: match command {
+                    ListCommands::Add {
+                        domain: Some(domain),
+                        file,
+                    } => ListCommands::Add {
+                        domain: Some(validate::list_entry(list, &domain)?),
+                        file,
+                    },
+                    ListCommands::Remove {
+                        domain: Some(domain),
+                        file,
+                    } => ListCommands::Remove {
+                        domain: Some(validate::list_entry(list, &domain)?),
+                        file,
+                    },
+                    ListCommands::Add { domain: None, .. }
+                    | ListCommands::Remove { domain: None, .. } => {
+                        return Err(Error::InvalidListCommand {
+                            message: "domains from a file are changed with a bulk list command, \
+                                      not a single call"
+                                .to_owned(),
+                        })
+                    }
+                    other => other,
+                },
+            },
+            Self::Dns { command } => Self::Dns {
+                command: match command {
+                    DnsCommands::Add { ip, domain } => DnsCommands::Add {
+                        ip,
+                        domain: validate::domain(&domain)?,
+                    },
+                    DnsCommands::Remove { ip, domain } => DnsCommands::Remove {
+                        ip,
+                        domain: validate::domain(&domain)?,
+                    },
+                    DnsCommands::Show => DnsCommands::Show,
+                },
+            },
+            Self::Cname { command } => Self::Cname {
+                command: match command {
+                    CnameCommands::Add {
+                        domain,
+                        target_domain,
+                    } => CnameCommands::Add {
+                        domain: validate::domain(&domain)?,
+                        target_domain: validate::domain(&target_domain)?,
+                    },
+                    CnameCommands::Remove {
+                        domain,
+                        target_domain,
+                    } => CnameCommands::Remove {
+                        domain: validate::domain(&domain)?,
+                        target_domain: validate::domain(&target_domain)?,
+                    },
+                    CnameCommands::Show => CnameCommands::Show,
+                },
+            },
+            other => other,
+        })
+    }
+}
+
 impl CallApi for ApiCommands {
-    fn call(&self, api: &PiHoleConfigImplementation) -> Result<APIResult, APIError> {
+    type Output = APIResult;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<APIResult, APIError> {
         match self {
             Self::Enable => api.get_authenticated_api()?.enable().map(|a| a.into()),
             Self::Disable { duration } => api
@@ -186,9 +383,10 @@ impl CallApi for ApiCommands {
                 .get_authenticated_api()?
                 .get_query_types()
                 .map(|a| a.into()),
-            Self::AllQueries { count } => api
+            Self::AllQueries { count, filter, .. } => api
                 .get_authenticated_api()?
                 .get_all_queries(*count)
+                .map(|queries| filter.apply(queries))
                 .map(|a| a.into()),
             Self::Cache => api
                 .get_authenticated_api()?
@@ -209,19 +407,43 @@ impl CallApi for ApiCommands {
                 .get_queries_count()
                 .map(QueriesCountWrapper)
                 .map(|a| a.into()),
-            Self::List { list, command } => match command {
-                ListCommands::Show => api
+            Self::List { list, command } => match (list, command) {
+                (list, ListCommands::Test { domain, query_type }) => {
+                    let lists = match list {
+                        Some(list) => vec![*list],
+                        None => TESTED_LISTS.to_vec(),
+                    };
+                    list_test::test_lists(api, &lists, domain, query_type).map(|a| a.into())
+                }
+                (None, _) => Err(APIError::InvalidList),
+                (Some(list), ListCommands::Show) => api
                     .get_authenticated_api()?
-                    .list_get_domains(list)
+                    .list_get_domains(list.as_str())
                     .map(|a| a.into()),
-                ListCommands::Add { domain } => api
+                (
+                    Some(list),
+                    ListCommands::Add {
+                        domain: Some(domain),
+                        file: _,
+                    },
+                ) => api
                     .get_authenticated_api()?
-                    .list_add(domain, list)
+                    .list_add(domain, list.as_str())
                     .map(|a| a.into()),
-                ListCommands::Remove { domain } => api
+                (
+                    Some(list),
+                    ListCommands::Remove {
+                        domain: Some(domain),
+                        file: _,
+                    },
+                ) => api
                     .get_authenticated_api()?
-                    .list_remove(domain, list)
+                    .list_remove(domain, list.as_str())
                     .map(|a| a.into()),
+                // Domains from a file are added and removed by a BulkListCommand, and a single
+                // call has no domain to send
+                (_, ListCommands::Add { domain: None, .. })
+                | (_, ListCommands::Remove { domain: None, .. }) => Err(APIError::InvalidList),
             },
             Self::Dns { command } => match command {
                 DnsCommands::Show => api
@@ -267,7 +489,7 @@ impl CallApi for ApiCommands {
 }
 
 impl ToTableTitleDynamic for ApiCommands {
-    fn to_table_title(&self) -> Vec<CellStruct> {
+    fn to_table_title(&self) -> Vec<TableCell> {
         match self {
             Self::Enable => Status::to_table_title(),
             Self::Disable { duration: _ } => Status::to_table_title(),
@@ -281,7 +503,7 @@ impl ToTableTitleDynamic for ApiCommands {
             Self::TopClientsBlocked { count: _ } => TopClientsBlocked::to_table_title(),
             Self::ForwardDestinations { unsorted: _ } => ForwardDestinations::to_table_title(),
             Self::QueryTypes => QueryTypes::to_table_title(),
-            Self::AllQueries { count: _ } => AllQueries::to_table_title(),
+            Self::AllQueries { .. } => AllQueries::to_table_title(),
             Self::Cache => CacheInfo::to_table_title(),
             Self::ClientNames => ClientName::to_table_title(),
             Self::OverTimeDataClients => OverTimeDataClientsWrapper::to_table_title(),
@@ -289,8 +511,9 @@ impl ToTableTitleDynamic for ApiCommands {
             Self::QueriesCount => QueriesCountWrapper::to_table_title(),
             Self::List { list: _, command } => match command {
                 ListCommands::Show => CustomListDomainDetails::to_table_title(),
-                ListCommands::Add { domain: _ } => ListModificationResponse::to_table_title(),
-                ListCommands::Remove { domain: _ } => ListModificationResponse::to_table_title(),
+                ListCommands::Add { .. } => ListModificationResponse::to_table_title(),
+                ListCommands::Remove { .. } => ListModificationResponse::to_table_title(),
+                ListCommands::Test { .. } => ListTest::to_table_title(),
             },
             Self::Dns { command } => match command {
                 DnsCommands::Show => CustomDNSRecord::to_table_title(),
@@ -315,20 +538,39 @@ impl ToTableTitleDynamic for ApiCommands {
     }
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
 pub enum ListCommands {
     Show,
     Add {
         /// Domain to add
-        domain: String,
+        #[clap(required_unless_present = "file")]
+        domain: Option<String>,
+        /// File of domains to add, one per line, or - for stdin. Blank lines and lines starting
+        /// with # are ignored
+        #[clap(short, long, conflicts_with = "domain")]
+        file: Option<PathBuf>,
     },
     Remove {
         /// Domain to remove
+        #[clap(required_unless_present = "file")]
+        domain: Option<String>,
+        /// File of domains to remove, one per line, or - for stdin. Blank lines and lines
+        /// starting with # are ignored
+        #[clap(short, long, conflicts_with = "domain")]
+        file: Option<PathBuf>,
+    },
+    /// Show which whitelist, regex and wildcard entries match a domain, evaluated locally as FTL
+    /// would
+    Test {
+        /// Domain to test
         domain: String,
+        /// Query type to test, which matters for entries with ;querytype=
+        #[clap(long, default_value = "A")]
+        query_type: String,
     },
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
 pub enum DnsCommands {
     Show,
     Add {
@@ -345,7 +587,7 @@ pub enum DnsCommands {
     },
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
 pub enum CnameCommands {
     Show,
     Add {
@@ -365,3 +607,197 @@ pub enum CnameCommands {
 fn parse_duration(arg: &str) -> Result<std::time::Duration, humantime::DurationError> {
     arg.parse::<humantime::Duration>().map(Into::into)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::FakePiHole;
+
+    fn list(list: ListName, command: ListCommands) -> ApiCommands {
+        ApiCommands::List {
+            list: Some(list),
+            command,
+        }
+    }
+
+    fn add(domain: &str) -> ListCommands {
+        ListCommands::Add {
+            domain: Some(domain.to_owned()),
+            file: None,
+        }
+    }
+
+    #[test]
+    fn enable_and_disable_change_the_status() {
+        let fake = FakePiHole::new();
+        let disable = ApiCommands::Disable {
+            duration: Duration::from_secs(60),
+        };
+        match disable.call(&fake).unwrap() {
+            APIResult::Status(status) => assert_eq!(status.status, "disabled"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(!fake.is_enabled());
+
+        match ApiCommands::Enable.call(&fake).unwrap() {
+            APIResult::Status(status) => assert_eq!(status.status, "enabled"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(fake.is_enabled());
+    }
+
+    #[test]
+    fn list_add_is_shown_and_removed() {
+        let fake = FakePiHole::new();
+        list(ListName::Black, add("ads.example.com"))
+            .call(&fake)
+            .unwrap();
+
+        match list(ListName::Black, ListCommands::Show)
+            .call(&fake)
+            .unwrap()
+        {
+            APIResult::CustomListDomainDetailsList(domains) => {
+                let domains: Vec<_> = domains.iter().map(|d| d.domain.as_str()).collect();
+                assert_eq!(domains, ["ads.example.com"]);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let remove = ListCommands::Remove {
+            domain: Some("ads.example.com".to_owned()),
+            file: None,
+        };
+        list(ListName::Black, remove).call(&fake).unwrap();
+        assert!(fake.list_domains(ListName::Black).is_empty());
+    }
+
+    #[test]
+    fn dns_and_cname_records_are_added_and_removed() {
+        let fake = FakePiHole::new();
+        let ip: IpAddr = "192.168.0.10".parse().unwrap();
+        let add_dns = ApiCommands::Dns {
+            command: DnsCommands::Add {
+                ip,
+                domain: "nas.lan".to_owned(),
+            },
+        };
+        add_dns.call(&fake).unwrap();
+        let add_cname = ApiCommands::Cname {
+            command: CnameCommands::Add {
+                domain: "files.lan".to_owned(),
+                target_domain: "nas.lan".to_owned(),
+            },
+        };
+        add_cname.call(&fake).unwrap();
+
+        match (ApiCommands::Dns {
+            command: DnsCommands::Show,
+        })
+        .call(&fake)
+        .unwrap()
+        {
+            APIResult::CustomDNSRecords(records) => {
+                assert_eq!(records.len(), 1);
+                assert_eq!(records[0].domain, "nas.lan");
+                assert_eq!(records[0].ip_address, ip);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        ApiCommands::Dns {
+            command: DnsCommands::Remove {
+                ip,
+                domain: "nas.lan".to_owned(),
+            },
+        }
+        .call(&fake)
+        .unwrap();
+        assert!(fake.dns_records().is_empty());
+        assert_eq!(
+            fake.cname_records(),
+            [("files.lan".to_owned(), "nas.lan".to_owned())]
+        );
+    }
+
+    #[test]
+    fn statistics_are_wrapped_in_their_result() {
+        let fake = FakePiHole::new().with_list_domain(ListName::Black, "ads.example.com");
+        match ApiCommands::SummaryRaw.call(&fake).unwrap() {
+            APIResult::SummaryRaw(summary) => {
+                assert_eq!(summary.domains_being_blocked, 1);
+                assert_eq!(summary.status, "enabled");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(matches!(
+            ApiCommands::Version.call(&fake).unwrap(),
+            APIResult::VersionWrapper(VersionWrapper(3))
+        ));
+        assert!(matches!(
+            ApiCommands::QueriesCount.call(&fake).unwrap(),
+            APIResult::QueriesCountWrapper(QueriesCountWrapper(0))
+        ));
+        assert!(matches!(
+            ApiCommands::Logage.call(&fake).unwrap(),
+            APIResult::LogageWrapper(_)
+        ));
+    }
+
+    #[test]
+    fn authenticated_commands_need_a_key() {
+        let fake = FakePiHole::new().without_key();
+        assert!(matches!(
+            ApiCommands::Enable.call(&fake),
+            Err(APIError::MissingAPIKey)
+        ));
+        assert!(matches!(
+            list(ListName::Black, ListCommands::Show).call(&fake),
+            Err(APIError::MissingAPIKey)
+        ));
+        // Summaries do not need a key
+        assert!(ApiCommands::Summary.call(&fake).is_ok());
+    }
+
+    #[test]
+    fn validation_rejects_bad_entries_before_calling() {
+        let invalid = list(ListName::Black, add("not a domain")).validated();
+        assert!(matches!(invalid, Err(Error::InvalidEntry { .. })));
+
+        let no_list = ApiCommands::List {
+            list: None,
+            command: ListCommands::Show,
+        }
+        .validated();
+        assert!(matches!(no_list, Err(Error::InvalidListCommand { .. })));
+
+        match list(ListName::Black, add("bücher.example")).validated() {
+            Ok(ApiCommands::List {
+                command: ListCommands::Add { domain, .. },
+                ..
+            }) => assert_eq!(domain.as_deref(), Some("xn--bcher-kva.example")),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn file_changes_are_not_single_calls() {
+        let from_file = || {
+            list(
+                ListName::Black,
+                ListCommands::Remove {
+                    domain: None,
+                    file: Some(PathBuf::from("blocklist.txt")),
+                },
+            )
+        };
+        assert!(matches!(
+            from_file().validated(),
+            Err(Error::InvalidListCommand { .. })
+        ));
+        assert!(matches!(
+            from_file().call(&FakePiHole::new()),
+            Err(APIError::InvalidList)
+        ));
+    }
+}
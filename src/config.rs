@@ -1,8 +1,14 @@
+use crate::api_util::{CallPolicy, PiHoleHost};
+use crate::error::Error;
 use directories::ProjectDirs;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
 use std::fs::File;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Default, Clone)]
 pub struct PiHoleCtlConfig {
@@ -15,34 +21,212 @@ pub struct PiHoleCtlConfig {
 #[derive(Debug, Deserialize, Clone)]
 pub struct HostKeyPair {
     pub host: String,
-    pub key: Option<String>,
+    pub key: Option<ApiKey>,
+    /// Overrides `--timeout` for this host e.g. "5s"
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub timeout: Option<Duration>,
+    /// Overrides `--retries` for this host
+    pub retries: Option<u32>,
 }
 
-pub fn get_config_file(override_path: &Option<PathBuf>, verbose: bool) -> PiHoleCtlConfig {
+/// API key for a Pi-Hole, which is redacted when debug printed e.g. by `--verbose`
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for ApiKey {
+    type Err = Infallible;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        Ok(ApiKey(key.to_owned()))
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKey(<redacted>)")
+    }
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|duration| humantime::parse_duration(&duration).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+pub fn get_config_file(
+    override_path: &Option<PathBuf>,
+    verbose: bool,
+) -> Result<PiHoleCtlConfig, Error> {
     // Use the override path instead of the default config if set
-    let project_paths = ProjectDirs::from("com", "scratchcat1", "piholectl")
-        .expect("Unable to determine the default config location");
-    let mut default_config_path = project_paths.config_dir().to_path_buf();
-    default_config_path.push("config.json");
-    let config_file_path = override_path.clone().unwrap_or(default_config_path);
+    let config_file_path = match override_path {
+        Some(path) => path.clone(),
+        None => {
+            let project_paths = ProjectDirs::from("com", "scratchcat1", "piholectl")
+                .ok_or(Error::ConfigLocation)?;
+            let mut default_config_path = project_paths.config_dir().to_path_buf();
+            default_config_path.push("config.json");
+            default_config_path
+        }
+    };
 
     if verbose {
-        println!("Using configuration path {}", config_file_path.display());
+        eprintln!("Using configuration path {}", config_file_path.display());
     }
 
-    let f = File::open(config_file_path);
+    match File::open(&config_file_path) {
+        Ok(reader) => serde_json::from_reader(&reader).map_err(|source| Error::ConfigParse {
+            path: config_file_path,
+            source,
+        }),
+        // Always error if the user provided path did not work
+        // If using the default path, ignore file not found errors.
+        Err(e) if override_path.is_none() && e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(PiHoleCtlConfig::default())
+        }
+        Err(source) => Err(Error::ConfigRead {
+            path: config_file_path,
+            source,
+        }),
+    }
+}
 
-    match f {
-        Ok(reader) => serde_json::from_reader(&reader).expect("Failed to parse config file"),
-        r @ Err(_) => {
-            // Always error if the user provided path did not work
-            // If using the default path, ignore file not found errors.
-            if override_path.is_some()
-                || r.as_ref().err().unwrap().kind() != std::io::ErrorKind::NotFound
-            {
-                r.expect("Error reading config file");
+impl HostKeyPair {
+    fn to_host(&self, name: &str, default_policy: &CallPolicy) -> PiHoleHost {
+        // Settings for an individual host take precedence over the command line options
+        let policy = CallPolicy {
+            timeout: self.timeout.or(default_policy.timeout),
+            retries: self.retries.unwrap_or(default_policy.retries),
+            ..default_policy.clone()
+        };
+        PiHoleHost::new(
+            self.host.clone(),
+            Some(name.to_owned()),
+            &self.key.clone().unwrap_or_default(),
+            policy,
+        )
+    }
+}
+
+/// Find a single host, either by its name in the config file or by its address in `hosts`
+pub fn resolve_host(
+    config: &PiHoleCtlConfig,
+    host: &str,
+    hosts: &[String],
+    keys: &[ApiKey],
+    default_policy: &CallPolicy,
+) -> Result<PiHoleHost, Error> {
+    if let Some(host_key_pair) = config.hosts.get(host) {
+        return Ok(host_key_pair.to_host(host, default_policy));
+    }
+    match hosts.iter().zip(keys).find(|(address, _)| *address == host) {
+        Some((address, key)) => Ok(PiHoleHost::new(
+            address.clone(),
+            None,
+            key,
+            default_policy.clone(),
+        )),
+        None => Err(Error::UnknownHost {
+            host: host.to_owned(),
+            available: config.hosts.keys().cloned().collect(),
+        }),
+    }
+}
+
+/// Combine the hosts given on the command line with the hosts of the selected groups.
+/// If no groups are selected the group named "default" is used, if it exists.
+pub fn resolve_hosts(
+    config: &PiHoleCtlConfig,
+    hosts: &[String],
+    keys: &[ApiKey],
+    groups: &[String],
+    default_policy: &CallPolicy,
+    verbose: bool,
+) -> Result<Vec<PiHoleHost>, Error> {
+    // Throw an error if the hosts and keys are not the same length
+    if hosts.len() != keys.len() {
+        return Err(Error::HostKeyMismatch {
+            hosts: hosts.len(),
+            keys: keys.len(),
+        });
+    }
+
+    // Hosts given on the command line use the policy from the command line options
+    let mut resolved: Vec<PiHoleHost> = hosts
+        .iter()
+        .zip(keys)
+        .map(|(host, key)| PiHoleHost::new(host.clone(), None, key, default_policy.clone()))
+        .collect();
+
+    // Select the group named "default" if no group is explicitly provided
+    let selected_groups = if groups.is_empty() {
+        config
+            .groups
+            .keys()
+            .filter(|group| *group == "default")
+            .cloned()
+            .collect()
+    } else {
+        groups.to_vec()
+    };
+    if verbose {
+        eprintln!("Selected groups: {:?}", selected_groups);
+    }
+
+    // Index of each named host in `resolved`
+    let mut included_hosts = HashMap::new();
+    for group in &selected_groups {
+        let named_hosts = config
+            .groups
+            .get(group)
+            .ok_or_else(|| Error::GroupNotFound {
+                group: group.clone(),
+                available: config.groups.keys().cloned().collect(),
+            })?;
+
+        for named_host in named_hosts {
+            // Only add each host once, but remember every group which selected it
+            if let Some(&index) = included_hosts.get(named_host) {
+                let host: &mut PiHoleHost = &mut resolved[index];
+                host.groups.push(group.clone());
+                continue;
             }
-            PiHoleCtlConfig::default()
+
+            let host_key_pair =
+                config
+                    .hosts
+                    .get(named_host)
+                    .ok_or_else(|| Error::HostNotFound {
+                        host: named_host.clone(),
+                        group: group.clone(),
+                        available: config.hosts.keys().cloned().collect(),
+                    })?;
+
+            if verbose {
+                eprintln!(
+                    "Adding host: {} with key: {:?}",
+                    host_key_pair.host, host_key_pair.key
+                );
+            }
+
+            let mut host = host_key_pair.to_host(named_host, default_policy);
+            host.groups.push(group.clone());
+            included_hosts.insert(named_host, resolved.len());
+            resolved.push(host);
         }
     }
+
+    if resolved.is_empty() {
+        return Err(Error::NoHosts);
+    }
+    Ok(resolved)
 }
@@ -0,0 +1,582 @@
+use crate::api_util::{self, APIResult, ApiCall, PiHoleBackend, PiHoleHost};
+use crate::commands::ApiCommands;
+use crate::error::HostError;
+use crate::follow::QueryTail;
+use crate::query_filter::StatusFilter;
+use pi_hole_api::api_types::{OverTimeData, Query, Summary, TopClientsBlocked, TopItems};
+use pi_hole_api::errors::APIError;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
+use ratatui::widgets::{
+    Axis, Block, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, TableState, Wrap,
+};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Queries fetched from each host on every refresh to find the new ones
+const QUERIES_PER_REFRESH: u32 = 100;
+/// Queries kept for the live stream
+const STREAM_LENGTH: usize = 200;
+/// Most hosts shown at once, further hosts are scrolled to
+const HOST_ROWS: usize = 8;
+/// How long to wait for a key press before checking for new results
+const KEY_POLL: Duration = Duration::from_millis(100);
+
+/// Responses of every host to the commands behind the panels, in the order of the hosts
+pub struct Snapshot {
+    pub summary: Vec<Result<Summary, HostError>>,
+    pub over_time: Vec<Result<OverTimeData, HostError>>,
+    pub top_items: Vec<Result<TopItems, HostError>>,
+    pub top_clients_blocked: Vec<Result<TopClientsBlocked, HostError>>,
+    pub queries: Vec<Result<Vec<Query>, HostError>>,
+}
+
+impl Snapshot {
+    /// Call every host with each of the commands behind the panels
+    pub fn fetch(hosts: &[PiHoleHost], count: u32, parallelism: usize) -> Snapshot {
+        let count = Some(count);
+        Snapshot {
+            summary: call(hosts, parallelism, |api| {
+                api.get_unauthenticated_api().get_summary()
+            }),
+            over_time: call(hosts, parallelism, |api| {
+                api.get_unauthenticated_api().get_over_time_data_10_mins()
+            }),
+            top_items: call(hosts, parallelism, |api| {
+                api.get_authenticated_api()?.get_top_items(&count)
+            }),
+            top_clients_blocked: call(hosts, parallelism, |api| {
+                api.get_authenticated_api()?.get_top_clients_blocked(count)
+            }),
+            queries: call(hosts, parallelism, |api| {
+                api.get_authenticated_api()?
+                    .get_all_queries(QUERIES_PER_REFRESH)
+            }),
+        }
+    }
+}
+
+/// Call every host with one of the requests behind the panels
+fn call<T, F>(hosts: &[PiHoleHost], parallelism: usize, call: F) -> Vec<Result<T, HostError>>
+where
+    T: Send,
+    F: Fn(&dyn PiHoleBackend) -> Result<T, APIError> + Sync,
+{
+    api_util::call_hosts(&ApiCall(call), hosts, parallelism, false)
+}
+
+/// Sent to the dashboard by the threads which call the hosts
+pub enum Message {
+    Refreshed(Snapshot),
+    /// Blocking was enabled or disabled on a host
+    Changed {
+        host: usize,
+        action: String,
+        result: Result<APIResult, HostError>,
+    },
+}
+
+/// What a key press asks for, beyond changing what is shown
+pub enum Request {
+    Quit,
+    Refresh,
+    /// Call the host at an index, e.g. to enable or disable blocking
+    Call {
+        host: usize,
+        command: ApiCommands,
+        action: String,
+    },
+}
+
+/// State of the dashboard, updated by key presses and the results of each refresh
+pub struct Dashboard {
+    names: Vec<String>,
+    latest: Option<Snapshot>,
+    tails: Vec<QueryTail>,
+    /// New queries of every host, newest first, with the index of their host
+    stream: VecDeque<(usize, Query)>,
+    selected: usize,
+    /// Duration typed so far after asking to disable blocking
+    prompt: Option<String>,
+    /// Outcome of the last action, and whether it failed
+    status: Option<(String, bool)>,
+    refreshed: Option<SystemTime>,
+}
+
+impl Dashboard {
+    pub fn new(hosts: &[PiHoleHost]) -> Self {
+        Dashboard {
+            names: hosts
+                .iter()
+                .map(|host| host.name.clone().unwrap_or_else(|| host.host.clone()))
+                .collect(),
+            latest: None,
+            tails: hosts.iter().map(|_| QueryTail::default()).collect(),
+            stream: VecDeque::new(),
+            selected: 0,
+            prompt: None,
+            status: None,
+            refreshed: None,
+        }
+    }
+
+    pub fn receive(&mut self, message: Message) {
+        match message {
+            Message::Refreshed(mut snapshot) => {
+                let mut new = Vec::new();
+                let queries = std::mem::take(&mut snapshot.queries);
+                for (host, (tail, result)) in self.tails.iter_mut().zip(queries).enumerate() {
+                    if let Ok(queries) = result {
+                        let poll = tail.poll(queries, QUERIES_PER_REFRESH as usize);
+                        new.extend(poll.new.into_iter().map(|query| (host, query)));
+                    }
+                }
+                new.sort_by_key(|(_, query)| query.timestring);
+                for entry in new {
+                    self.stream.push_front(entry);
+                }
+                self.stream.truncate(STREAM_LENGTH);
+                self.latest = Some(snapshot);
+                self.refreshed = Some(SystemTime::now());
+            }
+            Message::Changed {
+                host,
+                action,
+                result,
+            } => {
+                let name = &self.names[host];
+                self.status = Some(match result {
+                    Ok(APIResult::Status(status)) => {
+                        (format!("{}: blocking {}", name, status.status), false)
+                    }
+                    Ok(_) => (format!("{}: {} done", name, action), false),
+                    Err(error) => (format!("{}: unable to {}: {}", name, action, error), true),
+                });
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Request> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Request::Quit);
+        }
+        if self.prompt.is_some() {
+            return self.handle_prompt_key(key);
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Request::Quit),
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.names.len().saturating_sub(1))
+            }
+            KeyCode::Char('r') => return Some(Request::Refresh),
+            KeyCode::Char('e') => return Some(self.call(ApiCommands::Enable, "enable".to_owned())),
+            KeyCode::Char('d') => self.prompt = Some(String::new()),
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_prompt_key(&mut self, key: KeyEvent) -> Option<Request> {
+        match key.code {
+            KeyCode::Enter => {
+                let input = self.prompt.take().unwrap_or_default();
+                match input.trim().parse::<humantime::Duration>() {
+                    Ok(duration) => {
+                        let command = ApiCommands::Disable {
+                            duration: duration.into(),
+                        };
+                        return Some(self.call(command, format!("disable for {}", duration)));
+                    }
+                    Err(error) => {
+                        self.status =
+                            Some((format!("'{}' is not a duration: {}", input, error), true))
+                    }
+                }
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Backspace => {
+                if let Some(input) = &mut self.prompt {
+                    input.pop();
+                }
+            }
+            KeyCode::Char(c) => self.prompt.get_or_insert_with(String::new).push(c),
+            _ => {}
+        }
+        None
+    }
+
+    fn call(&mut self, command: ApiCommands, action: String) -> Request {
+        self.status = Some((
+            format!("{}: sending {}", self.names[self.selected], action),
+            false,
+        ));
+        Request::Call {
+            host: self.selected,
+            command,
+            action,
+        }
+    }
+
+    /// Result of the selected host for one of the panels, if a refresh has finished
+    fn selected_result<T>(
+        &self,
+        panel: fn(&Snapshot) -> &Vec<Result<T, HostError>>,
+    ) -> Option<&Result<T, HostError>> {
+        self.latest
+            .as_ref()
+            .and_then(|snapshot| panel(snapshot).get(self.selected))
+    }
+
+    pub fn render(&self, frame: &mut Frame) {
+        let host_rows = self.names.len().min(HOST_ROWS) as u16;
+        let [hosts, middle, queries, footer] = Layout::vertical([
+            Constraint::Length(host_rows + 3),
+            Constraint::Min(10),
+            Constraint::Min(6),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [chart, domains, clients] = Layout::horizontal([
+            Constraint::Percentage(50),
+            Constraint::Percentage(25),
+            Constraint::Percentage(25),
+        ])
+        .areas(middle);
+
+        self.render_hosts(frame, hosts);
+        self.render_over_time(frame, chart);
+        let top_ads = self
+            .selected_result(|snapshot| &snapshot.top_items)
+            .map(|result| result.as_ref().map(|items| ranked(&items.top_ads)));
+        render_ranking(frame, domains, "Top blocked domains", top_ads);
+        let top_clients = self
+            .selected_result(|snapshot| &snapshot.top_clients_blocked)
+            .map(|result| {
+                result
+                    .as_ref()
+                    .map(|clients| ranked(&clients.top_sources_blocked))
+            });
+        render_ranking(frame, clients, "Top blocked clients", top_clients);
+        self.render_queries(frame, queries);
+        self.render_footer(frame, footer);
+    }
+
+    fn render_hosts(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.names.iter().enumerate().map(|(index, name)| {
+            let summary = self
+                .latest
+                .as_ref()
+                .map(|snapshot| &snapshot.summary[index]);
+            let cells = match summary {
+                None => vec![Cell::from(name.as_str()), Cell::from("waiting")],
+                Some(Ok(summary)) => {
+                    let color = if summary.status == "enabled" {
+                        Color::Green
+                    } else {
+                        Color::Red
+                    };
+                    vec![
+                        Cell::from(name.as_str()),
+                        Cell::from(summary.status.as_str()).style(Style::new().fg(color)),
+                        Cell::from(summary.dns_queries_today.as_str()),
+                        Cell::from(summary.ads_blocked_today.as_str()),
+                        Cell::from(format!("{}%", summary.ads_percentage_today)),
+                        Cell::from(summary.domains_being_blocked.as_str()),
+                    ]
+                }
+                Some(Err(error)) => vec![
+                    Cell::from(name.as_str()),
+                    Cell::from("error").style(Style::new().fg(Color::Red)),
+                    Cell::from(error.to_string()).style(Style::new().fg(Color::Red)),
+                ],
+            };
+            Row::new(cells)
+        });
+        let header = Row::new([
+            "Host",
+            "status",
+            "queries today",
+            "blocked today",
+            "blocked",
+            "blocklist",
+        ])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Length(11),
+                Constraint::Fill(1),
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(header)
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title("Hosts"));
+        let mut state = TableState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+
+    fn render_over_time(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(format!(
+            "Queries per 10 minutes - {}",
+            self.names[self.selected]
+        ));
+        let data = match self.selected_result(|snapshot| &snapshot.over_time) {
+            Some(Ok(data)) => data,
+            Some(Err(error)) => return render_placeholder(frame, area, block, Some(error)),
+            None => return render_placeholder(frame, area, block, None),
+        };
+        let queries = points(&data.domains_over_time);
+        let blocked = points(&data.ads_over_time);
+        let (Some(first), Some(last)) = (queries.first(), queries.last()) else {
+            let empty = Paragraph::new("No queries yet").block(block);
+            return frame.render_widget(empty, area);
+        };
+        let (start, end) = (first.0, last.0);
+        let top = queries.iter().map(|(_, count)| *count).fold(1.0, f64::max);
+
+        let chart = Chart::new(vec![
+            Dataset::default()
+                .name("queries")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::new().fg(Color::Cyan))
+                .data(&queries),
+            Dataset::default()
+                .name("blocked")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::new().fg(Color::Red))
+                .data(&blocked),
+        ])
+        .block(block)
+        .x_axis(Axis::default().bounds([start, end]).labels([
+            clock(start),
+            clock((start + end) / 2.0),
+            clock(end),
+        ]))
+        .y_axis(
+            Axis::default()
+                .bounds([0.0, top])
+                .labels(["0".to_owned(), format!("{}", top as u64)]),
+        );
+        frame.render_widget(chart, area);
+    }
+
+    fn render_queries(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title("Live queries, all hosts");
+        if self.stream.is_empty() {
+            let waiting = Paragraph::new("Waiting for new queries").block(block);
+            return frame.render_widget(waiting, area);
+        }
+        let rows = self.stream.iter().map(|(host, query)| {
+            let color = if StatusFilter::Blocked.matches(&query.status) {
+                Color::Red
+            } else {
+                Color::Green
+            };
+            Row::new([
+                Cell::from(query.timestring.time().to_string()),
+                Cell::from(self.names[*host].as_str()),
+                Cell::from(format!("{:?}", query.query_type)),
+                Cell::from(query.domain.as_str()),
+                Cell::from(query.client.as_str()),
+                Cell::from(format!("{:?}", query.status)).style(Style::new().fg(color)),
+            ])
+        });
+        let header = Row::new(["time", "Host", "type", "domain", "client", "status"])
+            .style(Style::new().add_modifier(Modifier::BOLD));
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(8),
+                Constraint::Fill(1),
+                Constraint::Length(6),
+                Constraint::Fill(3),
+                Constraint::Fill(1),
+                Constraint::Length(24),
+            ],
+        )
+        .header(header)
+        .block(block);
+        frame.render_widget(table, area);
+    }
+
+    fn render_footer(&self, frame: &mut Frame, area: Rect) {
+        let footer = if let Some(input) = &self.prompt {
+            Paragraph::new(format!(
+                "Disable blocking on {} for (e.g. 30s, 5m, 1h): {}_   Enter to confirm, Esc to cancel",
+                self.names[self.selected], input
+            ))
+        } else {
+            let refreshed = self
+                .refreshed
+                .map(|time| format!("refreshed {}", humantime::format_rfc3339_seconds(time)))
+                .unwrap_or_else(|| "refreshing".to_owned());
+            let keys = "↑/↓ select  e enable  d disable  r refresh  q quit";
+            match &self.status {
+                Some((status, failed)) => {
+                    let color = if *failed { Color::Red } else { Color::Reset };
+                    Paragraph::new(format!("{}   {}", status, keys)).style(Style::new().fg(color))
+                }
+                None => Paragraph::new(format!("{}   {}", keys, refreshed)),
+            }
+        };
+        frame.render_widget(footer, area);
+    }
+}
+
+/// Entries of a top list, largest first
+fn ranked(counts: &HashMap<String, u64>) -> Vec<(String, u64)> {
+    let mut ranked: Vec<(String, u64)> = counts
+        .iter()
+        .map(|(key, count)| (key.clone(), *count))
+        .collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    ranked
+}
+
+fn render_ranking(
+    frame: &mut Frame,
+    area: Rect,
+    title: &str,
+    entries: Option<Result<Vec<(String, u64)>, &HostError>>,
+) {
+    let block = Block::bordered().title(title.to_owned());
+    let entries = match entries {
+        Some(Ok(entries)) => entries,
+        Some(Err(error)) => return render_placeholder(frame, area, block, Some(error)),
+        None => return render_placeholder(frame, area, block, None),
+    };
+    let rows = entries
+        .into_iter()
+        .map(|(key, count)| Row::new([count.to_string(), key]));
+    let table = Table::new(rows, [Constraint::Length(8), Constraint::Fill(1)]).block(block);
+    frame.render_widget(table, area);
+}
+
+/// Fill a panel whose data has not arrived or could not be fetched
+fn render_placeholder(frame: &mut Frame, area: Rect, block: Block, error: Option<&HostError>) {
+    let text = match error {
+        None => Paragraph::new("waiting"),
+        Some(error) => Paragraph::new(error.to_string()).style(Style::new().fg(Color::Red)),
+    };
+    frame.render_widget(text.wrap(Wrap { trim: true }).block(block), area);
+}
+
+/// Points of a time series keyed by Unix timestamp, in time order
+fn points(series: &HashMap<String, u64>) -> Vec<(f64, f64)> {
+    let mut points: Vec<(f64, f64)> = series
+        .iter()
+        .filter_map(|(time, count)| Some((time.parse().ok()?, *count as f64)))
+        .collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points
+}
+
+/// Time of day in UTC of a Unix timestamp, e.g. 18:40
+fn clock(timestamp: f64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(timestamp.max(0.0) as u64);
+    humantime::format_rfc3339_seconds(time).to_string()[11..16].to_owned()
+}
+
+/// Show the dashboard for the hosts until the user quits, refreshing every `interval`.
+/// `count` is the number of top blocked domains and clients fetched from each host.
+pub fn run(
+    hosts: Vec<PiHoleHost>,
+    interval: Duration,
+    count: u32,
+    parallelism: usize,
+) -> io::Result<()> {
+    let hosts = Arc::new(hosts);
+    let mut dashboard = Dashboard::new(&hosts);
+    let (messages, received) = mpsc::channel();
+    let (refresh, refresh_requested) = mpsc::channel::<()>();
+
+    let fetch_hosts = Arc::clone(&hosts);
+    let fetch_messages = messages.clone();
+    thread::spawn(move || loop {
+        let snapshot = Snapshot::fetch(&fetch_hosts, count, parallelism);
+        if fetch_messages.send(Message::Refreshed(snapshot)).is_err() {
+            return;
+        }
+        match refresh_requested.recv_timeout(interval) {
+            // Several requests made during a refresh only need one more refresh
+            Ok(()) => while refresh_requested.try_recv().is_ok() {},
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    });
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(
+        &mut terminal,
+        &mut dashboard,
+        &hosts,
+        &received,
+        &messages,
+        &refresh,
+    );
+    ratatui::restore();
+    result
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    dashboard: &mut Dashboard,
+    hosts: &Arc<Vec<PiHoleHost>>,
+    received: &Receiver<Message>,
+    messages: &Sender<Message>,
+    refresh: &Sender<()>,
+) -> io::Result<()> {
+    loop {
+        while let Ok(message) = received.try_recv() {
+            dashboard.receive(message);
+        }
+        terminal.draw(|frame| dashboard.render(frame))?;
+
+        if !event::poll(KEY_POLL)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match dashboard.handle_key(key) {
+            Some(Request::Quit) => return Ok(()),
+            Some(Request::Refresh) => {
+                let _ = refresh.send(());
+            }
+            Some(Request::Call {
+                host,
+                command,
+                action,
+            }) => {
+                let hosts = Arc::clone(hosts);
+                let messages = messages.clone();
+                let refresh = refresh.clone();
+                thread::spawn(move || {
+                    let result = api_util::call_with_policy(&command, &hosts[host]);
+                    let _ = messages.send(Message::Changed {
+                        host,
+                        action,
+                        result,
+                    });
+                    let _ = refresh.send(());
+                });
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use crate::api_util::{APIResult, ListName, PiHoleHost};
+use crate::commands::{ApiCommands, CnameCommands, DnsCommands, ListCommands};
+use crate::error::HostError;
+use crate::table::{TableCell, ToTableCell};
+use clap::Subcommand;
+use cli_table::Color;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// What `diff` compares across hosts
+#[derive(Clone, Debug, Subcommand)]
+pub enum DiffTarget {
+    /// Custom DNS records
+    Dns,
+    /// Custom CNAME records
+    Cname,
+    /// Domains on a black/whitelist
+    List {
+        /// List to compare
+        #[clap(arg_enum)]
+        list: ListName,
+    },
+    /// Versions of core, FTL and web
+    Versions,
+}
+
+impl DiffTarget {
+    /// Command which fetches the entries to compare from each host
+    pub fn command(&self) -> ApiCommands {
+        match self {
+            Self::Dns => ApiCommands::Dns {
+                command: DnsCommands::Show,
+            },
+            Self::Cname => ApiCommands::Cname {
+                command: CnameCommands::Show,
+            },
+            Self::List { list } => ApiCommands::List {
+                list: Some(*list),
+                command: ListCommands::Show,
+            },
+            Self::Versions => ApiCommands::Versions,
+        }
+    }
+
+    /// Columns which identify an entry
+    fn entry_columns(&self) -> Vec<&'static str> {
+        match self {
+            Self::Dns => vec!["domain", "ip"],
+            Self::Cname => vec!["domain", "target"],
+            Self::List { .. } => vec!["domain"],
+            Self::Versions => vec!["field"],
+        }
+    }
+
+    /// Entries are either present or missing, rather than having a value on each host
+    fn is_presence(&self) -> bool {
+        !matches!(self, Self::Versions)
+    }
+
+    /// Split a result into its entries, keyed by the entry columns
+    fn entries(&self, result: &APIResult) -> BTreeMap<Vec<String>, String> {
+        match result {
+            APIResult::CustomDNSRecords(records) => records
+                .iter()
+                .map(|record| {
+                    let key = vec![record.domain.clone(), record.ip_address.to_string()];
+                    (key, String::new())
+                })
+                .collect(),
+            APIResult::CustomCNAMERecords(records) => records
+                .iter()
+                .map(|record| {
+                    let key = vec![record.domain.clone(), record.target_domain.clone()];
+                    (key, String::new())
+                })
+                .collect(),
+            APIResult::CustomListDomainDetailsList(details) => details
+                .iter()
+                .map(|details| (vec![details.domain.clone()], String::new()))
+                .collect(),
+            other => match serde_json::to_value(other) {
+                Ok(Value::Object(fields)) => fields
+                    .into_iter()
+                    .map(|(field, value)| (vec![field], display_value(value)))
+                    .collect(),
+                _ => BTreeMap::new(),
+            },
+        }
+    }
+}
+
+fn display_value(value: Value) -> String {
+    match value {
+        Value::String(text) => text,
+        other => other.to_string(),
+    }
+}
+
+/// Entries which are missing or different on some of the hosts
+pub struct Diff {
+    target: DiffTarget,
+    /// Name, or address if it has no name, of each host which responded
+    hosts: Vec<String>,
+    /// Value of each differing entry on each host, `None` where the host does not have it
+    rows: Vec<(Vec<String>, Vec<Option<String>>)>,
+}
+
+impl Diff {
+    /// Compare the results of the hosts which responded. Failed hosts are left out.
+    pub fn new(
+        target: DiffTarget,
+        hosts: &[PiHoleHost],
+        results: &[Result<APIResult, HostError>],
+    ) -> Self {
+        let (labels, entries): (Vec<String>, Vec<_>) = hosts
+            .iter()
+            .zip(results)
+            .filter_map(|(host, result)| {
+                let label = host.name.clone().unwrap_or_else(|| host.host.clone());
+                result
+                    .as_ref()
+                    .ok()
+                    .map(|result| (label, target.entries(result)))
+            })
+            .unzip();
+
+        let keys: BTreeSet<&Vec<String>> = entries.iter().flat_map(|map| map.keys()).collect();
+        let rows = keys
+            .into_iter()
+            .map(|key| {
+                let values: Vec<Option<String>> =
+                    entries.iter().map(|map| map.get(key).cloned()).collect();
+                (key.clone(), values)
+            })
+            .filter(|(_, values)| values.iter().any(|value| value != &values[0]))
+            .collect();
+
+        Diff {
+            target,
+            hosts: unique_labels(labels),
+            rows,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn to_table_title(&self) -> Vec<TableCell> {
+        self.target
+            .entry_columns()
+            .into_iter()
+            .map(|column| column.cell())
+            .chain(self.hosts.iter().map(|host| host.cell()))
+            .collect()
+    }
+
+    pub fn to_table_rows(&self) -> Vec<Vec<TableCell>> {
+        self.rows
+            .iter()
+            .map(|(key, values)| {
+                let entry = key.iter().map(|column| column.cell());
+                let cells = values.iter().map(|value| match value {
+                    Some(_) if self.target.is_presence() => {
+                        "✓".cell().foreground_color(Some(Color::Green))
+                    }
+                    None if self.target.is_presence() => {
+                        "✗".cell().foreground_color(Some(Color::Red))
+                    }
+                    Some(value) => value.cell(),
+                    None => "-".cell(),
+                });
+                entry.chain(cells).collect()
+            })
+            .collect()
+    }
+
+    /// One record per differing entry, mapping each host to whether it has the entry, or to
+    /// its value for versions
+    pub fn to_records(&self) -> Vec<DiffRecord> {
+        self.rows
+            .iter()
+            .map(|(key, values)| {
+                let entry = self
+                    .target
+                    .entry_columns()
+                    .into_iter()
+                    .map(str::to_owned)
+                    .zip(key.iter().cloned().map(Value::String))
+                    .collect();
+                let hosts = self
+                    .hosts
+                    .iter()
+                    .cloned()
+                    .zip(values.iter().map(|value| match value {
+                        Some(_) if self.target.is_presence() => Value::Bool(true),
+                        None if self.target.is_presence() => Value::Bool(false),
+                        Some(value) => Value::String(value.clone()),
+                        None => Value::Null,
+                    }))
+                    .collect();
+                DiffRecord { entry, hosts }
+            })
+            .collect()
+    }
+}
+
+/// Hosts may share a name, or be given twice, but each needs its own column and JSON key, so
+/// repeated labels are numbered e.g. `pi (2)`
+fn unique_labels(labels: Vec<String>) -> Vec<String> {
+    let mut used = HashSet::new();
+    labels
+        .into_iter()
+        .map(|label| {
+            let mut unique = label.clone();
+            let mut occurrence = 1;
+            while !used.insert(unique.clone()) {
+                occurrence += 1;
+                unique = format!("{} ({})", label, occurrence);
+            }
+            unique
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffRecord {
+    #[serde(flatten)]
+    pub entry: Map<String, Value>,
+    pub hosts: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_util::CallPolicy;
+    use crate::fake::FakePiHole;
+    use pi_hole_api::api_types::{CustomDNSRecord, Versions};
+    use pi_hole_api::errors::APIError;
+    use std::sync::Arc;
+
+    fn host(name: &str) -> PiHoleHost {
+        PiHoleHost::with_backend(
+            format!("http://{}", name),
+            Some(name.to_owned()),
+            Arc::new(FakePiHole::new()),
+            CallPolicy::default(),
+        )
+    }
+
+    fn dns(records: &[(&str, &str)]) -> Result<APIResult, HostError> {
+        Ok(APIResult::CustomDNSRecords(
+            records
+                .iter()
+                .map(|(domain, ip)| CustomDNSRecord {
+                    domain: domain.to_string(),
+                    ip_address: ip.parse().unwrap(),
+                })
+                .collect(),
+        ))
+    }
+
+    fn versions(ftl: &str) -> Result<APIResult, HostError> {
+        Ok(APIResult::Versions(Versions {
+            core_update: false,
+            web_update: false,
+            ftl_update: false,
+            core_current: "v5.18".to_owned(),
+            web_current: "v5.21".to_owned(),
+            ftl_current: ftl.to_owned(),
+            core_latest: "v5.18".to_owned(),
+            web_latest: "v5.21".to_owned(),
+            ftl_latest: "v5.25".to_owned(),
+            core_branch: "master".to_owned(),
+            web_branch: "master".to_owned(),
+            ftl_branch: "master".to_owned(),
+        }))
+    }
+
+    fn texts(rows: &[Vec<TableCell>]) -> Vec<Vec<&str>> {
+        rows.iter()
+            .map(|row| row.iter().map(|cell| cell.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn entries_show_which_hosts_have_them() {
+        let diff = Diff::new(
+            DiffTarget::Dns,
+            &[host("one"), host("two")],
+            &[
+                dns(&[("nas.lan", "10.0.0.2"), ("pi.lan", "10.0.0.3")]),
+                dns(&[("pi.lan", "10.0.0.3"), ("tv.lan", "10.0.0.4")]),
+            ],
+        );
+        assert_eq!(
+            texts(&[diff.to_table_title()]),
+            [["domain", "ip", "one", "two"]]
+        );
+        assert_eq!(
+            texts(&diff.to_table_rows()),
+            [
+                ["nas.lan", "10.0.0.2", "✓", "✗"],
+                ["tv.lan", "10.0.0.4", "✗", "✓"]
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(diff.to_records()).unwrap(),
+            serde_json::json!([
+                {"domain": "nas.lan", "ip": "10.0.0.2", "hosts": {"one": true, "two": false}},
+                {"domain": "tv.lan", "ip": "10.0.0.4", "hosts": {"one": false, "two": true}}
+            ])
+        );
+    }
+
+    #[test]
+    fn versions_show_their_value_on_each_host() {
+        let diff = Diff::new(
+            DiffTarget::Versions,
+            &[host("one"), host("two")],
+            &[versions("v5.25"), versions("v5.23")],
+        );
+        assert_eq!(
+            texts(&diff.to_table_rows()),
+            [["FTL_current", "v5.25", "v5.23"]]
+        );
+        assert_eq!(
+            serde_json::to_value(diff.to_records()).unwrap(),
+            serde_json::json!([
+                {"field": "FTL_current", "hosts": {"one": "v5.25", "two": "v5.23"}}
+            ])
+        );
+    }
+
+    #[test]
+    fn failed_hosts_are_left_out() {
+        let diff = Diff::new(
+            DiffTarget::Dns,
+            &[host("one"), host("down"), host("two")],
+            &[
+                dns(&[("pi.lan", "10.0.0.3")]),
+                Err(HostError::from(APIError::FTLNotRunning)),
+                dns(&[("pi.lan", "10.0.0.3")]),
+            ],
+        );
+        assert!(diff.is_empty());
+        assert_eq!(
+            texts(&[diff.to_table_title()]),
+            [["domain", "ip", "one", "two"]]
+        );
+    }
+
+    #[test]
+    fn identical_hosts_have_no_differences() {
+        let diff = Diff::new(
+            DiffTarget::Versions,
+            &[host("one"), host("two")],
+            &[versions("v5.25"), versions("v5.25")],
+        );
+        assert!(diff.is_empty());
+        assert!(diff.to_records().is_empty());
+    }
+
+    #[test]
+    fn hosts_sharing_a_name_get_their_own_key() {
+        let diff = Diff::new(
+            DiffTarget::Dns,
+            &[host("pi"), host("pi")],
+            &[dns(&[("pi.lan", "10.0.0.3")]), dns(&[])],
+        );
+        assert_eq!(
+            serde_json::to_value(diff.to_records()).unwrap(),
+            serde_json::json!([
+                {"domain": "pi.lan", "ip": "10.0.0.3", "hosts": {"pi": true, "pi (2)": false}}
+            ])
+        );
+    }
+}
@@ -0,0 +1,364 @@
+use crate::exit_code;
+use crate::output::OutputFormat;
+use clap::ArgEnum;
+use pi_hole_api::errors::APIError;
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Errors which stop piholectl from running a command at all
+#[derive(Debug)]
+pub enum Error {
+    /// The platform specific configuration directory could not be determined
+    ConfigLocation,
+    /// The configuration file exists but could not be read
+    ConfigRead { path: PathBuf, source: io::Error },
+    /// The configuration file is not valid JSON or has an unexpected structure
+    ConfigParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// A selected group is not defined in the configuration file
+    GroupNotFound {
+        group: String,
+        available: Vec<String>,
+    },
+    /// A group refers to a named host which is not defined in the configuration file
+    HostNotFound {
+        host: String,
+        group: String,
+        available: Vec<String>,
+    },
+    /// A host is neither defined in the configuration file nor given with `--hosts`
+    UnknownHost {
+        host: String,
+        available: Vec<String>,
+    },
+    /// The number of `--hosts` and `--keys` options differ
+    HostKeyMismatch { hosts: usize, keys: usize },
+    /// Neither the command line nor the configuration file selected any hosts
+    NoHosts,
+    /// A command which works on a single host was given several
+    SingleHostRequired { command: &'static str, count: usize },
+    /// The recording directory or the local server used to record or replay could not be set up
+    Recording { path: PathBuf, source: io::Error },
+    /// `--replay` was given a directory without a recording for a host
+    RecordingNotFound { host: String, path: PathBuf },
+    /// The state file could not be read
+    StateRead { path: PathBuf, source: io::Error },
+    /// The state file is not valid YAML or has an unexpected structure
+    StateParse {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    /// The state file is well formed but describes something which cannot be applied
+    StateInvalid { path: PathBuf, message: String },
+    /// An option which keeps running was combined with an output format it cannot write
+    UnsupportedOutput {
+        option: &'static str,
+        output: OutputFormat,
+        /// The output formats which the option can write
+        supported: &'static str,
+    },
+    /// `--watch` was given with a command which does not simply read from each host
+    UnsupportedWatch,
+    /// `--aggregate` was given with a command whose results cannot be combined
+    UnsupportedAggregate,
+    /// The terminal could not be set up or drawn to by the dashboard
+    Terminal { source: io::Error },
+    /// A list subcommand was given a list it cannot work on, or no list where one is needed
+    InvalidListCommand { message: String },
+    /// A domain or regex was rejected before being sent to any host
+    InvalidEntry { entry: String, message: String },
+    /// The file of domains for a bulk list change could not be read
+    DomainsRead { path: PathBuf, source: io::Error },
+    /// The backup file could not be read
+    BackupRead { path: PathBuf, source: io::Error },
+    /// The backup file is not valid JSON or has an unexpected structure
+    BackupParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// The backup file is well formed but describes something which cannot be imported
+    BackupInvalid { path: PathBuf, message: String },
+    /// A call to a host failed
+    Host(HostError),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Host(error) if error.is_authentication_error() => exit_code::AUTHENTICATION,
+            Self::Host(_) => exit_code::TOTAL_FAILURE,
+            _ => exit_code::USAGE,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConfigLocation => write!(
+                f,
+                "unable to determine the default config location, pass one with --config-file-path"
+            ),
+            Self::ConfigRead { path, source } => write!(
+                f,
+                "unable to read config file {}: {}",
+                path.display(),
+                source
+            ),
+            Self::ConfigParse { path, source } => write!(
+                f,
+                "config file {} is not valid: {} (see the Configuration section of the README)",
+                path.display(),
+                source
+            ),
+            Self::GroupNotFound { group, available } => write!(
+                f,
+                "group '{}' not found in the config file ({})",
+                group,
+                describe_available("groups", available)
+            ),
+            Self::HostNotFound {
+                host,
+                group,
+                available,
+            } => write!(
+                f,
+                "group '{}' refers to host '{}' which is not defined under \"hosts\" ({})",
+                group,
+                host,
+                describe_available("hosts", available)
+            ),
+            Self::UnknownHost { host, available } => write!(
+                f,
+                "host '{}' is neither defined in the config file nor given with --hosts ({})",
+                host,
+                describe_available("hosts", available)
+            ),
+            Self::HostKeyMismatch { hosts, keys } => write!(
+                f,
+                "{} --hosts but {} --keys were given, pass one --keys per host (use 'none' for hosts without a key)",
+                hosts, keys
+            ),
+            Self::NoHosts => write!(
+                f,
+                "no hosts selected, pass --hosts and --keys or define a 'default' group in the config file"
+            ),
+            Self::SingleHostRequired { command, count } => write!(
+                f,
+                "{} works on a single host but {} were selected, choose one with --hosts or --groups",
+                command, count
+            ),
+            Self::Recording { path, source } => write!(
+                f,
+                "unable to record or replay responses in {}: {}",
+                path.display(),
+                source
+            ),
+            Self::RecordingNotFound { host, path } => write!(
+                f,
+                "no recording for host {} in {}, record one with --record",
+                host,
+                path.display()
+            ),
+            Self::StateRead { path, source } => write!(
+                f,
+                "unable to read state file {}: {}",
+                path.display(),
+                source
+            ),
+            Self::StateParse { path, source } => write!(
+                f,
+                "state file {} is not valid: {} (see the State Files section of the README)",
+                path.display(),
+                source
+            ),
+            Self::StateInvalid { path, message } => {
+                write!(f, "state file {} is not valid: {}", path.display(), message)
+            }
+            Self::UnsupportedOutput {
+                option,
+                output,
+                supported,
+            } => write!(
+                f,
+                "{} cannot write {} output, use {}",
+                option,
+                output
+                    .to_possible_value()
+                    .map(|value| value.get_name())
+                    .unwrap_or_default(),
+                supported
+            ),
+            Self::UnsupportedWatch => write!(
+                f,
+                "--watch repeats commands which call each host, so it cannot be used with plan, apply, dashboard, diff, export, import, sync, list add/remove --file or all-queries --follow"
+            ),
+            Self::UnsupportedAggregate => write!(
+                f,
+                "--aggregate can only combine summary, summary-raw, top-items, top-clients, top-clients-blocked, query-types and forward-destinations"
+            ),
+            Self::Terminal { source } => write!(f, "unable to draw the dashboard: {}", source),
+            Self::InvalidListCommand { message } => write!(f, "{}", message),
+            Self::InvalidEntry { entry, message } => {
+                write!(f, "'{}' was not sent to any host: {}", entry, message)
+            }
+            Self::DomainsRead { path, source } => write!(
+                f,
+                "unable to read domains from {}: {}",
+                path.display(),
+                source
+            ),
+            Self::BackupRead { path, source } => write!(
+                f,
+                "unable to read backup file {}: {}",
+                path.display(),
+                source
+            ),
+            Self::BackupParse { path, source } => write!(
+                f,
+                "backup file {} is not valid: {} (see the Backups section of the README)",
+                path.display(),
+                source
+            ),
+            Self::BackupInvalid { path, message } => {
+                write!(f, "backup file {} is not valid: {}", path.display(), message)
+            }
+            Self::Host(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<HostError> for Error {
+    fn from(error: HostError) -> Self {
+        Error::Host(error)
+    }
+}
+
+fn describe_available(what: &str, available: &[String]) -> String {
+    if available.is_empty() {
+        format!("no {} are defined", what)
+    } else {
+        let mut available = available.to_vec();
+        available.sort();
+        format!("available {}: {}", what, available.join(", "))
+    }
+}
+
+/// Errors from calling a single host, which are reported alongside the results of the other hosts
+#[derive(Debug)]
+pub enum HostError {
+    /// The host could not be reached or the connection failed before a response was received
+    Transport(APIError),
+    /// The host responded, but with an error status, an error from the API or a response which
+    /// could not be read
+    Api(APIError),
+    /// No response was received within the timeout on any attempt
+    Timeout { after: Duration, attempts: u32 },
+    /// The host was not called because an earlier host failed
+    Skipped,
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(error) | Self::Api(error) => match error {
+                APIError::RequestError(error) => write!(f, "{}", redact_api_keys(&error.to_string())),
+                APIError::IntoJsonError(error) => write!(f, "{}", error),
+                APIError::SerdeJSONError(error) => write!(f, "{}", error),
+                APIError::MissingAPIKey => write!(
+                    f,
+                    "An API key is required for this command, but none was given or the host rejected it"
+                ),
+                APIError::InvalidList => write!(f, "The list name is not valid"),
+                APIError::FTLNotRunning => write!(f, "FTL is not running on the host"),
+            },
+            Self::Timeout { after, attempts } => write!(
+                f,
+                "Timeout: no response within {} ({} attempt{})",
+                humantime::format_duration(*after),
+                attempts,
+                if *attempts == 1 { "" } else { "s" }
+            ),
+            Self::Skipped => write!(f, "Skipped after an earlier host failed"),
+        }
+    }
+}
+
+impl HostError {
+    /// Stable identifier for the type of error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Transport(error) | Self::Api(error) => match error {
+                APIError::RequestError(_) => "RequestError",
+                APIError::IntoJsonError(_) => "IntoJsonError",
+                APIError::SerdeJSONError(_) => "SerdeJSONError",
+                APIError::MissingAPIKey => "MissingAPIKey",
+                APIError::InvalidList => "InvalidList",
+                APIError::FTLNotRunning => "FTLNotRunning",
+            },
+            Self::Timeout { .. } => "Timeout",
+            Self::Skipped => "Skipped",
+        }
+    }
+
+    /// Human readable description of the error
+    pub fn message(&self) -> String {
+        self.to_string()
+    }
+
+    /// Whether the call failed because the API key was missing or the host rejected it
+    pub fn is_authentication_error(&self) -> bool {
+        matches!(self, Self::Api(APIError::MissingAPIKey))
+    }
+}
+
+impl From<APIError> for HostError {
+    fn from(error: APIError) -> Self {
+        match error {
+            // An error status is a response from the host, so only failures to reach it are
+            // worth retrying
+            APIError::RequestError(ureq::Error::Transport(_)) => HostError::Transport(error),
+            _ => HostError::Api(error),
+        }
+    }
+}
+
+/// Hide the value of any `auth` query parameter, as request errors include the URL
+fn redact_api_keys(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(position) = rest.find("auth=") {
+        let (before, after) = rest.split_at(position + "auth=".len());
+        redacted.push_str(before);
+        redacted.push_str("<redacted>");
+        let end = after
+            .find(|c: char| matches!(c, '&' | '"' | ':') || c.is_whitespace())
+            .unwrap_or(after.len());
+        rest = &after[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// Serialisable form of a `HostError`
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl From<&HostError> for ErrorReport {
+    fn from(error: &HostError) -> Self {
+        ErrorReport {
+            kind: error.kind(),
+            message: error.message(),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+// Process exit codes, so that scripts can tell how a run went from `$?`
+
+/// Every host succeeded
+pub const SUCCESS: i32 = 0;
+/// Every host failed
+pub const TOTAL_FAILURE: i32 = 1;
+/// The command line or configuration file is invalid. Matches the code clap uses for usage errors.
+pub const USAGE: i32 = 2;
+/// Some, but not all, hosts failed
+pub const PARTIAL_FAILURE: i32 = 3;
+/// At least one host failed because an API key was required
+pub const AUTHENTICATION: i32 = 4;
+
+/// Exit code for a run where `failed` out of `total` hosts failed.
+/// Authentication failures take precedence as they need fixing in the configuration.
+pub fn for_outcome(total: usize, failed: usize, authentication_failed: bool) -> i32 {
+    if authentication_failed {
+        AUTHENTICATION
+    } else if failed == 0 {
+        SUCCESS
+    } else if failed == total {
+        TOTAL_FAILURE
+    } else {
+        PARTIAL_FAILURE
+    }
+}
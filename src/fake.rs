@@ -0,0 +1,514 @@
+use crate::api_util::{wildcard_regex, ListName, PiHoleBackend};
+use clap::ArgEnum;
+use pi_hole_api::{
+    api_types::*, errors::APIError, AuthenticatedPiHoleAPI, UnauthenticatedPiHoleAPI,
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// In-memory Pi-Hole which can be used in place of a real instance.
+/// Changes made through the list, DNS, CNAME, enable and disable endpoints are kept so they are
+/// visible to later calls. The statistics endpoints return fixed, mostly empty, data.
+pub struct FakePiHole {
+    state: Mutex<FakeState>,
+    authenticated: bool,
+    failure: Option<fn() -> APIError>,
+    rejected: Vec<String>,
+    delay: Option<Duration>,
+}
+
+struct FakeState {
+    enabled: bool,
+    lists: HashMap<ListName, Vec<String>>,
+    dns_records: Vec<(String, IpAddr)>,
+    cname_records: Vec<(String, String)>,
+}
+
+impl Default for FakePiHole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakePiHole {
+    /// An enabled Pi-Hole with an API key and no custom records
+    pub fn new() -> Self {
+        FakePiHole {
+            state: Mutex::new(FakeState {
+                enabled: true,
+                lists: HashMap::new(),
+                dns_records: Vec::new(),
+                cname_records: Vec::new(),
+            }),
+            authenticated: true,
+            failure: None,
+            rejected: Vec::new(),
+            delay: None,
+        }
+    }
+
+    /// Authenticated calls fail with `APIError::MissingAPIKey`
+    pub fn without_key(mut self) -> Self {
+        self.authenticated = false;
+        self
+    }
+
+    /// Every call fails with the error returned by `failure`
+    pub fn failing_with(mut self, failure: fn() -> APIError) -> Self {
+        self.failure = Some(failure);
+        self
+    }
+
+    /// Every call waits for `delay` before responding, e.g. to exercise concurrent calls
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Adds which include `domain` respond with `success: false` and change nothing, as a
+    /// Pi-Hole does for a domain it considers invalid
+    pub fn rejecting(mut self, domain: &str) -> Self {
+        self.rejected.push(domain.to_owned());
+        self
+    }
+
+    pub fn with_list_domain(self, list: ListName, domain: &str) -> Self {
+        self.state()
+            .lists
+            .entry(list)
+            .or_default()
+            .push(domain.to_owned());
+        self
+    }
+
+    pub fn with_dns_record(self, ip: IpAddr, domain: &str) -> Self {
+        self.state().dns_records.push((domain.to_owned(), ip));
+        self
+    }
+
+    pub fn with_cname_record(self, domain: &str, target_domain: &str) -> Self {
+        self.state()
+            .cname_records
+            .push((domain.to_owned(), target_domain.to_owned()));
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state().enabled
+    }
+
+    pub fn list_domains(&self, list: ListName) -> Vec<String> {
+        self.state().lists.get(&list).cloned().unwrap_or_default()
+    }
+
+    /// Custom DNS records as (domain, ip) pairs
+    pub fn dns_records(&self) -> Vec<(String, IpAddr)> {
+        self.state().dns_records.clone()
+    }
+
+    /// Custom CNAME records as (domain, target domain) pairs
+    pub fn cname_records(&self) -> Vec<(String, String)> {
+        self.state().cname_records.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().expect("Fake Pi-Hole state was poisoned")
+    }
+
+    /// Applies the configured delay and failure before each call
+    fn respond(&self) -> Result<(), APIError> {
+        if let Some(delay) = self.delay {
+            thread::sleep(delay);
+        }
+        match self.failure {
+            Some(failure) => Err(failure()),
+            None => Ok(()),
+        }
+    }
+
+    /// The list an entry is stored in, and its stored form. Like a Pi-Hole, wildcard entries are
+    /// stored as regexes in the regex list.
+    fn stored(list: &str, domain: &str) -> Result<(ListName, String), APIError> {
+        let list = ListName::from_str(list, false).map_err(|_| APIError::InvalidList)?;
+        Ok(match list.is_wild() {
+            true => (list.stored_in(), wildcard_regex(domain)),
+            false => (list, domain.to_owned()),
+        })
+    }
+
+    fn status(&self) -> String {
+        match self.state().enabled {
+            true => "enabled".to_owned(),
+            false => "disabled".to_owned(),
+        }
+    }
+
+    fn set_enabled(&self, enabled: bool) -> Result<Status, APIError> {
+        self.respond()?;
+        self.state().enabled = enabled;
+        Ok(Status {
+            status: self.status(),
+        })
+    }
+}
+
+fn modification(success: bool, message: String) -> ListModificationResponse {
+    ListModificationResponse {
+        success,
+        message: Some(message),
+    }
+}
+
+impl PiHoleBackend for FakePiHole {
+    fn get_unauthenticated_api(&self) -> &dyn UnauthenticatedPiHoleAPI {
+        self
+    }
+
+    fn get_authenticated_api(&self) -> Result<&dyn AuthenticatedPiHoleAPI, APIError> {
+        match self.authenticated {
+            true => Ok(self),
+            false => Err(APIError::MissingAPIKey),
+        }
+    }
+}
+
+impl UnauthenticatedPiHoleAPI for FakePiHole {
+    fn get_summary_raw(&self) -> Result<SummaryRaw, APIError> {
+        self.respond()?;
+        Ok(SummaryRaw {
+            domains_being_blocked: self.list_domains(ListName::Black).len() as u64,
+            dns_queries_today: 0,
+            ads_blocked_today: 0,
+            ads_percentage_today: 0.0,
+            unique_domains: 0,
+            queries_forwarded: 0,
+            queries_cached: 0,
+            clients_ever_seen: 0,
+            unique_clients: 0,
+            dns_queries_all_types: 0,
+            reply_nodata: 0,
+            reply_nxdomain: 0,
+            reply_cname: 0,
+            reply_ip: 0,
+            privacy_level: 0,
+            status: self.status(),
+        })
+    }
+
+    fn get_summary(&self) -> Result<Summary, APIError> {
+        let raw = self.get_summary_raw()?;
+        Ok(Summary {
+            domains_being_blocked: raw.domains_being_blocked.to_string(),
+            dns_queries_today: raw.dns_queries_today.to_string(),
+            ads_blocked_today: raw.ads_blocked_today.to_string(),
+            ads_percentage_today: format!("{:.1}", raw.ads_percentage_today),
+            unique_domains: raw.unique_domains.to_string(),
+            queries_forwarded: raw.queries_forwarded.to_string(),
+            queries_cached: raw.queries_cached.to_string(),
+            clients_ever_seen: raw.clients_ever_seen.to_string(),
+            unique_clients: raw.unique_clients.to_string(),
+            dns_queries_all_types: raw.dns_queries_all_types.to_string(),
+            reply_nodata: raw.reply_nodata.to_string(),
+            reply_nxdomain: raw.reply_nxdomain.to_string(),
+            reply_cname: raw.reply_cname.to_string(),
+            reply_ip: raw.reply_ip.to_string(),
+            privacy_level: raw.privacy_level.to_string(),
+            status: raw.status,
+        })
+    }
+
+    fn get_over_time_data_10_mins(&self) -> Result<OverTimeData, APIError> {
+        self.respond()?;
+        Ok(OverTimeData {
+            domains_over_time: HashMap::new(),
+            ads_over_time: HashMap::new(),
+        })
+    }
+
+    fn get_version(&self) -> Result<u32, APIError> {
+        self.respond()?;
+        Ok(3)
+    }
+
+    fn get_versions(&self) -> Result<Versions, APIError> {
+        self.respond()?;
+        Ok(Versions {
+            core_update: false,
+            web_update: false,
+            ftl_update: false,
+            core_current: "v5.18".to_owned(),
+            web_current: "v5.21".to_owned(),
+            ftl_current: "v5.25".to_owned(),
+            core_latest: "v5.18".to_owned(),
+            web_latest: "v5.21".to_owned(),
+            ftl_latest: "v5.25".to_owned(),
+            core_branch: "master".to_owned(),
+            web_branch: "master".to_owned(),
+            ftl_branch: "master".to_owned(),
+        })
+    }
+}
+
+impl AuthenticatedPiHoleAPI for FakePiHole {
+    fn get_top_items(&self, _count: &Option<u32>) -> Result<TopItems, APIError> {
+        self.respond()?;
+        Ok(TopItems {
+            top_queries: HashMap::new(),
+            top_ads: HashMap::new(),
+        })
+    }
+
+    fn get_top_clients(&self, _count: &Option<u32>) -> Result<TopClients, APIError> {
+        self.respond()?;
+        Ok(TopClients {
+            top_sources: HashMap::new(),
+        })
+    }
+
+    fn get_top_clients_blocked(&self, _count: Option<u32>) -> Result<TopClientsBlocked, APIError> {
+        self.respond()?;
+        Ok(TopClientsBlocked {
+            top_sources_blocked: HashMap::new(),
+        })
+    }
+
+    fn get_forward_destinations(&self, _unsorted: bool) -> Result<ForwardDestinations, APIError> {
+        self.respond()?;
+        Ok(ForwardDestinations {
+            forward_destinations: HashMap::new(),
+        })
+    }
+
+    fn get_query_types(&self) -> Result<QueryTypes, APIError> {
+        self.respond()?;
+        Ok(QueryTypes {
+            querytypes: HashMap::new(),
+        })
+    }
+
+    fn get_all_queries(&self, _count: u32) -> Result<Vec<Query>, APIError> {
+        self.respond()?;
+        Ok(Vec::new())
+    }
+
+    fn enable(&self) -> Result<Status, APIError> {
+        self.set_enabled(true)
+    }
+
+    fn disable(&self, _seconds: u64) -> Result<Status, APIError> {
+        self.set_enabled(false)
+    }
+
+    fn get_cache_info(&self) -> Result<CacheInfo, APIError> {
+        self.respond()?;
+        Ok(CacheInfo {
+            cache_size: 10000,
+            cache_live_freed: 0,
+            cache_inserted: 0,
+        })
+    }
+
+    fn get_client_names(&self) -> Result<Vec<ClientName>, APIError> {
+        self.respond()?;
+        Ok(Vec::new())
+    }
+
+    fn get_over_time_data_clients(&self) -> Result<HashMap<String, Vec<u64>>, APIError> {
+        self.respond()?;
+        Ok(HashMap::new())
+    }
+
+    fn get_network(&self) -> Result<Network, APIError> {
+        self.respond()?;
+        Ok(Network {
+            network: Vec::new(),
+        })
+    }
+
+    fn get_queries_count(&self) -> Result<u64, APIError> {
+        self.respond()?;
+        Ok(0)
+    }
+
+    fn list_add(&self, domain: &str, list: &str) -> Result<ListModificationResponse, APIError> {
+        self.respond()?;
+        let (list, domain) = Self::stored(list, domain)?;
+        let added: Vec<&str> = if list.is_batched() {
+            domain.split_whitespace().collect()
+        } else {
+            vec![&domain]
+        };
+        if let Some(rejected) = added
+            .iter()
+            .find(|domain| self.rejected.iter().any(|r| r == *domain))
+        {
+            return Ok(modification(
+                false,
+                format!("{} is not a valid domain", rejected),
+            ));
+        }
+        let mut state = self.state();
+        let domains = state.lists.entry(list).or_default();
+        if added
+            .iter()
+            .all(|domain| domains.iter().any(|existing| existing == domain))
+        {
+            return Ok(modification(
+                true,
+                format!("{} is already on the {} list", domain, list),
+            ));
+        }
+        for domain in added {
+            if !domains.iter().any(|existing| existing == domain) {
+                domains.push(domain.to_owned());
+            }
+        }
+        Ok(modification(true, format!("Added {}", domain)))
+    }
+
+    fn list_remove(&self, domain: &str, list: &str) -> Result<ListModificationResponse, APIError> {
+        self.respond()?;
+        let (list, domain) = Self::stored(list, domain)?;
+        let mut state = self.state();
+        let domains = state.lists.entry(list).or_default();
+        domains.retain(|existing| *existing != domain);
+        Ok(modification(true, format!("Removed {}", domain)))
+    }
+
+    fn list_get_domains(&self, list: &str) -> Result<Vec<CustomListDomainDetails>, APIError> {
+        self.respond()?;
+        let (list, _) = Self::stored(list, "")?;
+        let domain_type = ListName::value_variants()
+            .iter()
+            .position(|name| *name == list)
+            .unwrap_or(0) as u64;
+        Ok(self
+            .list_domains(list)
+            .into_iter()
+            .enumerate()
+            .map(|(index, domain)| CustomListDomainDetails {
+                id: index as u64 + 1,
+                domain_type,
+                domain,
+                enabled: true,
+                date_added: Default::default(),
+                date_modified: Default::default(),
+                comment: String::new(),
+                groups: vec![0],
+            })
+            .collect())
+    }
+
+    fn get_custom_dns_records(&self) -> Result<Vec<CustomDNSRecord>, APIError> {
+        self.respond()?;
+        Ok(self
+            .dns_records()
+            .into_iter()
+            .map(|(domain, ip_address)| CustomDNSRecord { domain, ip_address })
+            .collect())
+    }
+
+    fn add_custom_dns_record(
+        &self,
+        ip: &IpAddr,
+        domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        self.respond()?;
+        let mut state = self.state();
+        if state
+            .dns_records
+            .iter()
+            .any(|(existing, existing_ip)| existing == domain && existing_ip == ip)
+        {
+            return Ok(modification(
+                false,
+                "This domain already has a custom DNS entry".to_owned(),
+            ));
+        }
+        state.dns_records.push((domain.to_owned(), *ip));
+        Ok(modification(true, String::new()))
+    }
+
+    fn delete_custom_dns_record(
+        &self,
+        ip: &IpAddr,
+        domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        self.respond()?;
+        let mut state = self.state();
+        let before = state.dns_records.len();
+        state
+            .dns_records
+            .retain(|(existing, existing_ip)| !(existing == domain && existing_ip == ip));
+        if state.dns_records.len() == before {
+            return Ok(modification(
+                false,
+                "This domain/ip association does not exist".to_owned(),
+            ));
+        }
+        Ok(modification(true, String::new()))
+    }
+
+    fn get_custom_cname_records(&self) -> Result<Vec<CustomCNAMERecord>, APIError> {
+        self.respond()?;
+        Ok(self
+            .cname_records()
+            .into_iter()
+            .map(|(domain, target_domain)| CustomCNAMERecord {
+                domain,
+                target_domain,
+            })
+            .collect())
+    }
+
+    fn add_custom_cname_record(
+        &self,
+        domain: &str,
+        target_domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        self.respond()?;
+        let mut state = self.state();
+        if state
+            .cname_records
+            .iter()
+            .any(|(existing, _)| existing == domain)
+        {
+            return Ok(modification(
+                false,
+                format!("There is already a CNAME record for '{}'", domain),
+            ));
+        }
+        state
+            .cname_records
+            .push((domain.to_owned(), target_domain.to_owned()));
+        Ok(modification(true, String::new()))
+    }
+
+    fn delete_custom_cname_record(
+        &self,
+        domain: &str,
+        target_domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        self.respond()?;
+        let mut state = self.state();
+        let before = state.cname_records.len();
+        state
+            .cname_records
+            .retain(|(existing, target)| !(existing == domain && target == target_domain));
+        if state.cname_records.len() == before {
+            return Ok(modification(
+                false,
+                "This domain/target association does not exist".to_owned(),
+            ));
+        }
+        Ok(modification(true, String::new()))
+    }
+
+    fn get_max_logage(&self) -> Result<f32, APIError> {
+        self.respond()?;
+        Ok(24.0)
+    }
+}
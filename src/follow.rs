@@ -0,0 +1,194 @@
+use pi_hole_api::api_types::Query;
+use std::collections::HashMap;
+
+/// Tracks the queries of one host which have already been seen, so that each poll of the query
+/// log only yields the queries which are new since the previous poll.
+#[derive(Debug, Default)]
+pub struct QueryTail {
+    /// Unix timestamp of the newest query seen
+    last: Option<i64>,
+    /// How many of each query were seen at the newest timestamp, as several queries, even
+    /// identical ones, can share a second
+    seen_at_last: HashMap<String, usize>,
+}
+
+/// What a poll of the query log found
+pub struct Poll {
+    pub new: Vec<Query>,
+    /// As many queries as were asked for were fetched and none had been seen before, so older
+    /// new queries may have been beyond the number fetched
+    pub may_have_missed: bool,
+}
+
+impl QueryTail {
+    /// Record the queries of a poll, returning those which had not been seen before in time
+    /// order. `limit` is the number of queries which were asked for. The first poll only sets
+    /// the starting point and returns nothing.
+    pub fn poll(&mut self, mut queries: Vec<Query>, limit: usize) -> Poll {
+        let first_poll = self.last.is_none();
+        let fetched = queries.len();
+        queries.sort_by_key(timestamp);
+        let newest = queries.last().map(timestamp);
+        let at_newest = count_keys(
+            queries
+                .iter()
+                .filter(|query| Some(timestamp(query)) == newest),
+        );
+
+        // Within the newest second already seen, only queries beyond the number of identical
+        // ones seen before are new
+        let mut repeats = HashMap::new();
+        let new: Vec<Query> = queries
+            .into_iter()
+            .filter(|query| match self.last {
+                None => true,
+                Some(last) => {
+                    let time = timestamp(query);
+                    if time != last {
+                        return time > last;
+                    }
+                    let key = key(query);
+                    let seen = self.seen_at_last.get(&key).copied().unwrap_or(0);
+                    let count = repeats.entry(key).or_insert(0);
+                    *count += 1;
+                    *count > seen
+                }
+            })
+            .collect();
+
+        if let Some(newest) = newest.filter(|newest| self.last.is_none_or(|last| *newest >= last)) {
+            self.seen_at_last = at_newest;
+            self.last = Some(newest);
+        }
+        // The first poll sets the starting point even if the host has no queries yet
+        self.last.get_or_insert(i64::MIN);
+
+        if first_poll {
+            return Poll {
+                new: Vec::new(),
+                may_have_missed: false,
+            };
+        }
+        Poll {
+            may_have_missed: fetched >= limit && new.len() == fetched,
+            new,
+        }
+    }
+}
+
+fn timestamp(query: &Query) -> i64 {
+    query.timestring.and_utc().timestamp()
+}
+
+/// Identifies a query within its second. The status is left out as it changes once an
+/// upstream server answers. Identical queries within a second share a key, so they are counted.
+fn key(query: &Query) -> String {
+    format!("{:?} {} {}", query.query_type, query.domain, query.client)
+}
+
+fn count_keys<'a>(queries: impl Iterator<Item = &'a Query>) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for query in queries {
+        *counts.entry(key(query)).or_insert(0) += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(time: i64, domain: &str) -> Query {
+        serde_json::from_value(serde_json::json!([
+            time.to_string(),
+            "A",
+            domain,
+            "192.168.0.2",
+            "2",
+            "0",
+            "4",
+            "12",
+            "",
+            "-1",
+            "8.8.8.8#53",
+            ""
+        ]))
+        .unwrap()
+    }
+
+    fn domains(poll: &Poll) -> Vec<(i64, &str)> {
+        poll.new
+            .iter()
+            .map(|query| (timestamp(query), query.domain.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn first_poll_sets_the_starting_point() {
+        let mut tail = QueryTail::default();
+        let first = tail.poll(vec![query(10, "a.example"), query(11, "b.example")], 100);
+        assert!(first.new.is_empty());
+        assert!(!first.may_have_missed);
+
+        // The log is newest first, but new queries are returned in time order
+        let second = tail.poll(
+            vec![
+                query(13, "d.example"),
+                query(12, "c.example"),
+                query(11, "b.example"),
+            ],
+            100,
+        );
+        assert_eq!(domains(&second), [(12, "c.example"), (13, "d.example")]);
+    }
+
+    #[test]
+    fn a_host_without_queries_returns_every_later_query() {
+        let mut tail = QueryTail::default();
+        assert!(tail.poll(Vec::new(), 100).new.is_empty());
+        let poll = tail.poll(vec![query(10, "a.example")], 100);
+        assert_eq!(domains(&poll), [(10, "a.example")]);
+    }
+
+    #[test]
+    fn queries_sharing_the_newest_second_are_returned_once() {
+        let mut tail = QueryTail::default();
+        tail.poll(vec![query(10, "a.example")], 100);
+        let poll = tail.poll(vec![query(10, "a.example"), query(10, "b.example")], 100);
+        assert_eq!(domains(&poll), [(10, "b.example")]);
+        assert!(tail
+            .poll(vec![query(10, "a.example"), query(10, "b.example")], 100)
+            .new
+            .is_empty());
+    }
+
+    #[test]
+    fn repeated_lookups_within_a_second_are_kept() {
+        let mut tail = QueryTail::default();
+        tail.poll(vec![query(10, "a.example")], 100);
+        let poll = tail.poll(vec![query(10, "a.example"), query(10, "a.example")], 100);
+        assert_eq!(domains(&poll), [(10, "a.example")]);
+
+        let poll = tail.poll(
+            vec![
+                query(11, "a.example"),
+                query(10, "a.example"),
+                query(10, "a.example"),
+            ],
+            100,
+        );
+        assert_eq!(domains(&poll), [(11, "a.example")]);
+    }
+
+    #[test]
+    fn a_full_poll_of_new_queries_may_have_missed_some() {
+        let mut tail = QueryTail::default();
+        tail.poll(vec![query(10, "a.example")], 2);
+        let full = tail.poll(vec![query(12, "c.example"), query(11, "b.example")], 2);
+        assert!(full.may_have_missed);
+
+        let overlapping = tail.poll(vec![query(13, "d.example"), query(12, "c.example")], 2);
+        assert_eq!(domains(&overlapping), [(13, "d.example")]);
+        assert!(!overlapping.may_have_missed);
+    }
+}
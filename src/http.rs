@@ -0,0 +1,335 @@
+use crate::api_util::PiHoleBackend;
+use pi_hole_api::{
+    api_types::*,
+    errors::{self, APIError},
+    AuthenticatedPiHoleAPI, UnauthenticatedPiHoleAPI,
+};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Talks to a Pi-Hole over HTTP.
+///
+/// The endpoints are the same as those of the `pi_hole_api` clients, but requests are made
+/// through an agent which gives up once the timeout has passed. A host which stops responding
+/// fails the call instead of leaving a request running in the background.
+pub struct PiHoleConfigImplementation {
+    host: String,
+    api_key: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl PiHoleConfigImplementation {
+    /// `host` must begin with the protocol e.g. http:// or https://. The timeout covers the
+    /// whole of each request, from connecting to reading the response.
+    pub fn new(host: String, api_key: Option<String>, timeout: Option<Duration>) -> Self {
+        let mut agent = ureq::AgentBuilder::new();
+        if let Some(timeout) = timeout {
+            agent = agent.timeout(timeout);
+        }
+        PiHoleConfigImplementation {
+            host,
+            api_key,
+            agent: agent.build(),
+        }
+    }
+
+    fn url(&self, path: &str, params: &[(&str, &str)]) -> String {
+        url::Url::parse_with_params(&format!("{}{}", self.host, path), params)
+            .map(String::from)
+            .unwrap_or_else(|_| format!("{}{}", self.host, path))
+    }
+
+    fn request<T: DeserializeOwned>(&self, params: &[(&str, &str)]) -> Result<T, APIError> {
+        Ok(self
+            .agent
+            .get(&self.url("/admin/api.php", params))
+            .call()?
+            .into_json()?)
+    }
+
+    fn authenticated_request<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T, APIError> {
+        let key = self.api_key.as_deref().ok_or(APIError::MissingAPIKey)?;
+        let mut params = params.to_vec();
+        params.push(("auth", key));
+        let response = self
+            .agent
+            .get(&self.url(path, &params))
+            .call()?
+            .into_string()?;
+        errors::detect_response_errors(&response)?;
+        serde_json::from_str(&response).map_err(|error| {
+            // A Pi-Hole responds to a wrong API key with an empty list rather than an error
+            if response.trim() == "[]" {
+                APIError::MissingAPIKey
+            } else {
+                error.into()
+            }
+        })
+    }
+}
+
+impl PiHoleBackend for PiHoleConfigImplementation {
+    fn get_unauthenticated_api(&self) -> &dyn UnauthenticatedPiHoleAPI {
+        self
+    }
+
+    fn get_authenticated_api(&self) -> Result<&dyn AuthenticatedPiHoleAPI, APIError> {
+        match self.api_key {
+            Some(_) => Ok(self),
+            None => Err(APIError::MissingAPIKey),
+        }
+    }
+}
+
+/// Responses which wrap their result in a `data` field
+#[derive(Deserialize)]
+struct Data<T> {
+    data: T,
+}
+
+/// The Pi-Hole responds with an empty list rather than an empty object when there is no data
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaybeEmpty<T> {
+    Map(HashMap<String, T>),
+    Empty(#[allow(dead_code)] Vec<serde::de::IgnoredAny>),
+}
+
+impl<T> From<MaybeEmpty<T>> for HashMap<String, T> {
+    fn from(value: MaybeEmpty<T>) -> Self {
+        match value {
+            MaybeEmpty::Map(map) => map,
+            MaybeEmpty::Empty(_) => HashMap::new(),
+        }
+    }
+}
+
+impl UnauthenticatedPiHoleAPI for PiHoleConfigImplementation {
+    fn get_summary_raw(&self) -> Result<SummaryRaw, APIError> {
+        self.request(&[("summaryRaw", "")])
+    }
+
+    fn get_summary(&self) -> Result<Summary, APIError> {
+        self.request(&[("summary", "")])
+    }
+
+    fn get_over_time_data_10_mins(&self) -> Result<OverTimeData, APIError> {
+        self.request(&[("overTimeData10mins", "")])
+    }
+
+    fn get_version(&self) -> Result<u32, APIError> {
+        self.request::<Version>(&[("version", "")])
+            .map(|version| version.version)
+    }
+
+    fn get_versions(&self) -> Result<Versions, APIError> {
+        self.request(&[("versions", "")])
+    }
+}
+
+impl AuthenticatedPiHoleAPI for PiHoleConfigImplementation {
+    fn get_top_items(&self, count: &Option<u32>) -> Result<TopItems, APIError> {
+        let count = count.unwrap_or(10).to_string();
+        self.authenticated_request("/admin/api.php", &[("topItems", &count)])
+    }
+
+    fn get_top_clients(&self, count: &Option<u32>) -> Result<TopClients, APIError> {
+        let count = count.unwrap_or(10).to_string();
+        self.authenticated_request("/admin/api.php", &[("topClients", &count)])
+    }
+
+    fn get_top_clients_blocked(&self, count: Option<u32>) -> Result<TopClientsBlocked, APIError> {
+        let count = count.unwrap_or(10).to_string();
+        self.authenticated_request("/admin/api.php", &[("topClientsBlocked", &count)])
+    }
+
+    fn get_forward_destinations(&self, unsorted: bool) -> Result<ForwardDestinations, APIError> {
+        let order = if unsorted { "unsorted" } else { "" };
+        self.authenticated_request("/admin/api.php", &[("getForwardDestinations", order)])
+    }
+
+    fn get_query_types(&self) -> Result<QueryTypes, APIError> {
+        self.authenticated_request("/admin/api.php", &[("getQueryTypes", "")])
+    }
+
+    fn get_all_queries(&self, count: u32) -> Result<Vec<Query>, APIError> {
+        let count = count.to_string();
+        self.authenticated_request::<Data<_>>("/admin/api.php", &[("getAllQueries", &count)])
+            .map(|response| response.data)
+    }
+
+    fn enable(&self) -> Result<Status, APIError> {
+        self.authenticated_request("/admin/api.php", &[("enable", "")])
+    }
+
+    fn disable(&self, seconds: u64) -> Result<Status, APIError> {
+        let seconds = seconds.to_string();
+        self.authenticated_request("/admin/api.php", &[("disable", &seconds)])
+    }
+
+    fn get_cache_info(&self) -> Result<CacheInfo, APIError> {
+        #[derive(Deserialize)]
+        struct Response {
+            cacheinfo: CacheInfo,
+        }
+        self.authenticated_request::<Response>("/admin/api.php", &[("getCacheInfo", "")])
+            .map(|response| response.cacheinfo)
+    }
+
+    fn get_client_names(&self) -> Result<Vec<ClientName>, APIError> {
+        #[derive(Deserialize)]
+        struct Response {
+            clients: Vec<ClientName>,
+        }
+        self.authenticated_request::<Response>("/admin/api.php", &[("getClientNames", "")])
+            .map(|response| response.clients)
+    }
+
+    fn get_over_time_data_clients(&self) -> Result<HashMap<String, Vec<u64>>, APIError> {
+        #[derive(Deserialize)]
+        struct Response {
+            over_time: MaybeEmpty<Vec<u64>>,
+        }
+        self.authenticated_request::<Response>("/admin/api.php", &[("overTimeDataClients", "")])
+            .map(|response| response.over_time.into())
+    }
+
+    fn get_network(&self) -> Result<Network, APIError> {
+        self.authenticated_request("/admin/api_db.php", &[("network", "")])
+    }
+
+    fn get_queries_count(&self) -> Result<u64, APIError> {
+        #[derive(Deserialize)]
+        struct Response {
+            count: u64,
+        }
+        self.authenticated_request::<Response>("/admin/api_db.php", &[("getQueriesCount", "")])
+            .map(|response| response.count)
+    }
+
+    fn list_add(&self, domain: &str, list: &str) -> Result<ListModificationResponse, APIError> {
+        self.authenticated_request("/admin/api.php", &[("add", domain), ("list", list)])
+    }
+
+    fn list_remove(&self, domain: &str, list: &str) -> Result<ListModificationResponse, APIError> {
+        self.authenticated_request("/admin/api.php", &[("sub", domain), ("list", list)])
+    }
+
+    fn list_get_domains(&self, list: &str) -> Result<Vec<CustomListDomainDetails>, APIError> {
+        self.authenticated_request::<Data<_>>("/admin/api.php", &[("get", ""), ("list", list)])
+            .map(|response| response.data)
+    }
+
+    fn get_custom_dns_records(&self) -> Result<Vec<CustomDNSRecord>, APIError> {
+        let records: Data<Vec<(String, String)>> =
+            self.authenticated_request("/admin/api.php", &[("customdns", ""), ("action", "get")])?;
+        records
+            .data
+            .into_iter()
+            .map(|(domain, ip)| {
+                let ip_address: IpAddr = ip.parse().map_err(|_| {
+                    serde_json::Error::custom(format!(
+                        "'{}' of {} is not an IP address",
+                        ip, domain
+                    ))
+                })?;
+                Ok(CustomDNSRecord { domain, ip_address })
+            })
+            .collect()
+    }
+
+    fn add_custom_dns_record(
+        &self,
+        ip: &IpAddr,
+        domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        let ip = ip.to_string();
+        self.authenticated_request(
+            "/admin/api.php",
+            &[
+                ("customdns", ""),
+                ("action", "add"),
+                ("ip", &ip),
+                ("domain", domain),
+            ],
+        )
+    }
+
+    fn delete_custom_dns_record(
+        &self,
+        ip: &IpAddr,
+        domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        let ip = ip.to_string();
+        self.authenticated_request(
+            "/admin/api.php",
+            &[
+                ("customdns", ""),
+                ("action", "delete"),
+                ("ip", &ip),
+                ("domain", domain),
+            ],
+        )
+    }
+
+    fn get_custom_cname_records(&self) -> Result<Vec<CustomCNAMERecord>, APIError> {
+        let records: Data<Vec<(String, String)>> = self
+            .authenticated_request("/admin/api.php", &[("customcname", ""), ("action", "get")])?;
+        Ok(records
+            .data
+            .into_iter()
+            .map(|(domain, target_domain)| CustomCNAMERecord {
+                domain,
+                target_domain,
+            })
+            .collect())
+    }
+
+    fn add_custom_cname_record(
+        &self,
+        domain: &str,
+        target_domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        self.authenticated_request(
+            "/admin/api.php",
+            &[
+                ("customcname", ""),
+                ("action", "add"),
+                ("domain", domain),
+                ("target", target_domain),
+            ],
+        )
+    }
+
+    fn delete_custom_cname_record(
+        &self,
+        domain: &str,
+        target_domain: &str,
+    ) -> Result<ListModificationResponse, APIError> {
+        self.authenticated_request(
+            "/admin/api.php",
+            &[
+                ("customcname", ""),
+                ("action", "delete"),
+                ("domain", domain),
+                ("target", target_domain),
+            ],
+        )
+    }
+
+    fn get_max_logage(&self) -> Result<f32, APIError> {
+        #[derive(Deserialize)]
+        struct Response {
+            maxlogage: f32,
+        }
+        self.authenticated_request::<Response>("/admin/api.php", &[("getMaxlogage", "")])
+            .map(|response| response.maxlogage)
+    }
+}
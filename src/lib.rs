@@ -0,0 +1,575 @@
+//! Control one or more Pi-Hole instances.
+//!
+//! The `piholectl` binary is a thin wrapper around [`run`]. The building blocks are public so
+//! other tools can reuse them:
+//! - [`config::get_config_file`] and [`config::resolve_hosts`] load the config and select hosts
+//! - [`api_util::call_hosts`] and [`api_util::call_each`] run a [`api_util::CallApi`] command
+//!   across many hosts
+//! - [`output::display`] and [`output::display_ndjson`] render the results
+//! - [`fake::FakePiHole`] is an in-memory [`api_util::PiHoleBackend`] for running commands
+//!   without a Pi-Hole
+//! - [`mock_server::MockServer`] serves the Pi-Hole HTTP API from fixture files
+//! - [`state`] plans and applies declarative state files
+//! - [`diff::Diff`] compares entries across hosts
+//! - [`aggregate::Aggregation`] totals the results of several hosts
+//! - [`backup::Backup`] exports and imports the managed configuration of a host
+//! - [`dashboard::Dashboard`] is the interactive view of `piholectl dashboard`
+//! - [`watch::Watch`] redraws a table in place, showing the change since the previous refresh
+//! - [`record::record_hosts`] and [`record::replay_hosts`] save and serve raw API responses
+
+// APIError comes from pi_hole_api and is large, but is passed by value throughout
+#![allow(clippy::result_large_err, clippy::large_enum_variant)]
+pub mod aggregate;
+pub mod api_type_wrappers;
+pub mod api_util;
+pub mod backup;
+pub mod bulk;
+pub mod commands;
+pub mod config;
+pub mod dashboard;
+pub mod diff;
+pub mod error;
+pub mod exit_code;
+pub mod fake;
+pub mod follow;
+pub mod http;
+pub mod list_test;
+pub mod mock_server;
+pub mod output;
+pub mod query_filter;
+pub mod record;
+pub mod state;
+pub mod table;
+pub mod validate;
+pub mod watch;
+
+pub use api_util::{
+    APIResult, CallApi, CallPolicy, ListName, PiHoleBackend, PiHoleConfigImplementation, PiHoleHost,
+};
+pub use commands::PiHoleCtlOptions;
+pub use config::{ApiKey, PiHoleCtlConfig};
+pub use error::{Error, HostError};
+pub use output::OutputFormat;
+
+use crate::aggregate::{Aggregate, Aggregation};
+use crate::api_util::ApiCall;
+use crate::backup::{Backup, ExportCommand};
+use crate::bulk::BulkListCommand;
+use crate::commands::{ApiCommands, Commands, ListCommands};
+use crate::diff::{Diff, DiffTarget};
+use crate::follow::QueryTail;
+use crate::query_filter::QueryFilter;
+use crate::state::{
+    Action, AppliedChange, Change, DesiredState, ReadStateCommand, StateCommand, SyncCommand,
+    SyncSummary,
+};
+use crate::table::{TableCell, ToTableRows, ToTableTitle, ToTableTitleDynamic};
+use crate::watch::Watch;
+use pi_hole_api::api_types::AllQueries;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Run the command against every selected host, returning the exit code
+pub fn run(opts: PiHoleCtlOptions) -> Result<i32, Error> {
+    let config = config::get_config_file(&opts.config_file_path, opts.verbose)?;
+    if opts.aggregate
+        && !matches!(&opts.command, Commands::Api(command) if Aggregation::of(command).is_some())
+    {
+        return Err(Error::UnsupportedAggregate);
+    }
+    if let Some(interval) = opts.watch {
+        return run_watch(&opts, &config, interval);
+    }
+    match &opts.command {
+        Commands::Api(ApiCommands::List {
+            list: Some(list),
+            command: ListCommands::Add {
+                file: Some(file), ..
+            },
+        }) => run_bulk(&opts, &config, *list, Action::Add, file),
+        Commands::Api(ApiCommands::List {
+            list: Some(list),
+            command: ListCommands::Remove {
+                file: Some(file), ..
+            },
+        }) => run_bulk(&opts, &config, *list, Action::Remove, file),
+        Commands::Api(ApiCommands::AllQueries {
+            count,
+            filter,
+            follow: true,
+            interval,
+        }) => run_follow(&opts, &config, *count, filter, *interval),
+        Commands::Api(command) if opts.aggregate => run_aggregate(&opts, &config, command),
+        Commands::Api(command) => {
+            let command = command.clone().validated()?;
+            let apis = select_hosts(&opts, &config, &opts.groups)?;
+            Ok(call_and_display(
+                &apis,
+                &opts,
+                command.to_table_title(),
+                |host| api_util::call_with_policy(&command, host),
+            ))
+        }
+        Commands::Plan { file } => run_state(&opts, &config, file, false),
+        Commands::Apply { file } => run_state(&opts, &config, file, true),
+        Commands::Diff { target } => run_diff(&opts, &config, target),
+        Commands::Export => run_export(&opts, &config),
+        Commands::Import { file } => {
+            let backup = backup::read_backup_file(file)?;
+            for (list, entry) in backup.disabled() {
+                eprintln!(
+                    "Skipping {} on the {} list, which is disabled in the backup",
+                    entry.domain, list
+                );
+            }
+            let apis = select_hosts(&opts, &config, &opts.groups)?;
+            let command = SyncCommand {
+                source: backup.desired_state(),
+                prune: false,
+            };
+            Ok(call_and_display(
+                &apis,
+                &opts,
+                SyncSummary::to_table_title(),
+                |host| api_util::call_with_policy(&command, host),
+            ))
+        }
+        Commands::Dashboard { interval, count } => {
+            if opts.output != OutputFormat::Table {
+                return Err(Error::UnsupportedOutput {
+                    option: "dashboard",
+                    output: opts.output,
+                    supported: "table",
+                });
+            }
+            let apis = select_hosts(&opts, &config, &opts.groups)?;
+            dashboard::run(apis, *interval, *count, opts.parallelism)
+                .map_err(|source| Error::Terminal { source })?;
+            Ok(exit_code::SUCCESS)
+        }
+        Commands::Sync { from, to, prune } => run_sync(&opts, &config, from, to, *prune),
+    }
+}
+
+fn default_policy(opts: &PiHoleCtlOptions) -> CallPolicy {
+    CallPolicy {
+        timeout: opts.timeout,
+        retries: opts.retries,
+        backoff: opts.backoff,
+    }
+}
+
+/// Resolve the hosts to call, routing them through a recording or replay if requested
+fn select_hosts(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    groups: &[String],
+) -> Result<Vec<PiHoleHost>, Error> {
+    let mut apis = config::resolve_hosts(
+        config,
+        &opts.hosts,
+        &opts.keys,
+        groups,
+        &default_policy(opts),
+        opts.verbose,
+    )?;
+    route_hosts(opts, &mut apis)?;
+    Ok(apis)
+}
+
+fn route_hosts(opts: &PiHoleCtlOptions, apis: &mut [PiHoleHost]) -> Result<(), Error> {
+    if let Some(directory) = &opts.record {
+        record::record_hosts(apis, directory)?;
+    }
+    if let Some(directory) = &opts.replay {
+        record::replay_hosts(apis, directory)?;
+    }
+    Ok(())
+}
+
+/// Add or remove every domain in a file on a list of the selected hosts
+fn run_bulk(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    list: ListName,
+    action: Action,
+    file: &Path,
+) -> Result<i32, Error> {
+    let domains = bulk::read_domains(file)?
+        .iter()
+        .map(|domain| validate::list_entry(list, domain))
+        .collect::<Result<_, _>>()?;
+    let command = BulkListCommand {
+        list,
+        action,
+        domains,
+    };
+    let apis = select_hosts(opts, config, &opts.groups)?;
+    Ok(call_and_display(
+        &apis,
+        opts,
+        command.to_table_title(),
+        |host| api_util::call_with_policy(&command, host),
+    ))
+}
+
+/// Poll the query log of the selected hosts until interrupted, writing the new queries of each
+/// poll. Queries are fetched unfiltered so that gaps between polls can be detected.
+fn run_follow(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    count: u32,
+    filter: &QueryFilter,
+    interval: Duration,
+) -> Result<i32, Error> {
+    if matches!(opts.output, OutputFormat::Json | OutputFormat::Yaml) {
+        return Err(Error::UnsupportedOutput {
+            option: "--follow",
+            output: opts.output,
+            supported: "table, csv, tsv, ndjson or ndjson-rows",
+        });
+    }
+    let apis = select_hosts(opts, config, &opts.groups)?;
+    let command =
+        ApiCall(|api: &dyn PiHoleBackend| api.get_authenticated_api()?.get_all_queries(count));
+    let mut tails: Vec<QueryTail> = apis.iter().map(|_| QueryTail::default()).collect();
+    let mut writer = output::RowWriter::new(AllQueries::to_table_title(), opts.output);
+
+    loop {
+        let results = api_util::call_hosts(&command, &apis, opts.parallelism, false);
+        let mut new = Vec::new();
+        for ((host, tail), result) in apis.iter().zip(&mut tails).zip(results) {
+            match result {
+                Ok(queries) => {
+                    let poll = tail.poll(queries, count as usize);
+                    if poll.may_have_missed {
+                        eprintln!(
+                            "{}: more than {} queries since the last poll, some may be missing. Raise COUNT or lower --interval",
+                            host.host, count
+                        );
+                    }
+                    new.push((host, filter.apply(poll.new)));
+                }
+                Err(error) if opts.output.is_streaming() => {
+                    output::display_ndjson(host, Err(error), false)
+                }
+                Err(error) => eprintln!("{}: {}", host.host, error),
+            }
+        }
+
+        if opts.output == OutputFormat::Ndjson {
+            for (host, queries) in new.into_iter().filter(|(_, q)| !q.is_empty()) {
+                output::display_ndjson(host, Ok(APIResult::AllQueries(queries)), false);
+            }
+        } else {
+            // Interleave the hosts in the order the queries were made
+            let mut queries: Vec<_> = new
+                .into_iter()
+                .flat_map(|(host, queries)| queries.into_iter().map(move |query| (host, query)))
+                .collect();
+            queries.sort_by_key(|(_, query)| query.timestring);
+            if opts.output == OutputFormat::NdjsonRows {
+                for (host, query) in queries {
+                    output::display_ndjson(host, Ok(APIResult::AllQueries(vec![query])), true);
+                }
+            } else {
+                writer.write(
+                    queries
+                        .into_iter()
+                        .flat_map(|(host, query)| query.to_table_rows(&host.host))
+                        .collect(),
+                );
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Call the selected hosts until interrupted, redrawing the table with the change since the
+/// previous refresh. Streaming output writes the results of every refresh instead.
+fn run_watch(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    interval: Duration,
+) -> Result<i32, Error> {
+    let command = match &opts.command {
+        Commands::Api(ApiCommands::AllQueries { follow: true, .. })
+        | Commands::Api(ApiCommands::List {
+            command:
+                ListCommands::Add { file: Some(_), .. } | ListCommands::Remove { file: Some(_), .. },
+            ..
+        }) => return Err(Error::UnsupportedWatch),
+        Commands::Api(command) => command,
+        _ => return Err(Error::UnsupportedWatch),
+    };
+    if !matches!(opts.output, OutputFormat::Table) && !opts.output.is_streaming() {
+        return Err(Error::UnsupportedOutput {
+            option: "--watch",
+            output: opts.output,
+            supported: "table, ndjson or ndjson-rows",
+        });
+    }
+    let command = command.clone().validated()?;
+    let aggregation = if opts.aggregate {
+        Some(Aggregation::of(&command).ok_or(Error::UnsupportedAggregate)?)
+    } else {
+        None
+    };
+    let apis = select_hosts(opts, config, &opts.groups)?;
+    let mut watch = Watch::new(interval, command.to_table_title());
+
+    loop {
+        if let Some(aggregation) = aggregation {
+            let (aggregate, results) = aggregation.call(&apis, opts.parallelism, opts.fail_fast);
+            let errors = host_errors(&apis, &results);
+            if opts.output.is_streaming() {
+                let records = aggregate.into_iter().collect();
+                output::display_report(vec![], vec![], records, &errors, opts.output);
+            } else {
+                let rows = aggregate.as_ref().map(Aggregate::to_table_rows);
+                watch.refresh(rows.unwrap_or_default(), output::error_lines(&errors));
+            }
+        } else {
+            let results = api_util::call_hosts(&command, &apis, opts.parallelism, opts.fail_fast);
+            if opts.output.is_streaming() {
+                let split_rows = opts.output == OutputFormat::NdjsonRows;
+                for (host, result) in apis.iter().zip(results) {
+                    output::display_ndjson(host, result, split_rows);
+                }
+            } else {
+                let (rows, errors) = output::table_rows(results, &apis);
+                watch.refresh(rows, errors);
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Combine the results of the selected hosts into one table
+fn run_aggregate(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    command: &ApiCommands,
+) -> Result<i32, Error> {
+    let aggregation = Aggregation::of(command).ok_or(Error::UnsupportedAggregate)?;
+    let apis = select_hosts(opts, config, &opts.groups)?;
+    let (aggregate, results) = aggregation.call(&apis, opts.parallelism, opts.fail_fast);
+
+    let errors = host_errors(&apis, &results);
+    let failed = errors.len();
+    let authentication_failed = errors.iter().any(|(_, e)| e.is_authentication_error());
+    output::display_report(
+        command.to_table_title(),
+        aggregate
+            .as_ref()
+            .map(Aggregate::to_table_rows)
+            .unwrap_or_default(),
+        aggregate.into_iter().collect(),
+        &errors,
+        opts.output,
+    );
+    Ok(exit_code::for_outcome(
+        apis.len(),
+        failed,
+        authentication_failed,
+    ))
+}
+
+/// Hosts which failed, with their error
+fn host_errors<'a, T>(
+    apis: &'a [PiHoleHost],
+    results: &'a [Result<T, HostError>],
+) -> Vec<(&'a PiHoleHost, &'a HostError)> {
+    apis.iter()
+        .zip(results)
+        .filter_map(|(host, result)| result.as_ref().err().map(|error| (host, error)))
+        .collect()
+}
+
+/// Plan or apply a state file against the hosts of the groups it declares
+fn run_state(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    file: &Path,
+    apply: bool,
+) -> Result<i32, Error> {
+    let state = state::read_state_file(file)?;
+
+    // Manage every group in the state file unless hosts or groups were chosen explicitly
+    let groups: Vec<String> = if opts.groups.is_empty() && opts.hosts.is_empty() {
+        state.keys().cloned().collect()
+    } else {
+        opts.groups.clone()
+    };
+    let apis = select_hosts(opts, config, &groups)?;
+    // Hosts given with --hosts belong to no group, so they match the chosen groups, or every
+    // group in the state file if none were chosen
+    let host_groups: Vec<String> = if groups.is_empty() {
+        state.keys().cloned().collect()
+    } else {
+        groups
+    };
+
+    let title = if apply {
+        AppliedChange::to_table_title()
+    } else {
+        Change::to_table_title()
+    };
+    Ok(call_and_display(&apis, opts, title, |host| {
+        let command = StateCommand {
+            desired: DesiredState::for_host(&state, host, &host_groups),
+            apply,
+        };
+        api_util::call_with_policy(&command, host)
+    }))
+}
+
+/// Compare entries across the selected hosts
+fn run_diff(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    target: &DiffTarget,
+) -> Result<i32, Error> {
+    let apis = select_hosts(opts, config, &opts.groups)?;
+    let command = target.command();
+    let results = api_util::call_hosts(&command, &apis, opts.parallelism, opts.fail_fast);
+
+    let errors = host_errors(&apis, &results);
+    let failed = errors.len();
+    let authentication_failed = errors.iter().any(|(_, e)| e.is_authentication_error());
+
+    let diff = Diff::new(target.clone(), &apis, &results);
+    if diff.is_empty() && failed < apis.len() && opts.output == OutputFormat::Table {
+        println!("No differences between the hosts which responded");
+    }
+    output::display_report(
+        diff.to_table_title(),
+        diff.to_table_rows(),
+        diff.to_records(),
+        &errors,
+        opts.output,
+    );
+    Ok(exit_code::for_outcome(
+        apis.len(),
+        failed,
+        authentication_failed,
+    ))
+}
+
+/// Write the backup of the single selected host to stdout
+fn run_export(opts: &PiHoleCtlOptions, config: &PiHoleCtlConfig) -> Result<i32, Error> {
+    let apis = select_hosts(opts, config, &opts.groups)?;
+    let host = match apis.as_slice() {
+        [host] => host,
+        _ => {
+            return Err(Error::SingleHostRequired {
+                command: "export",
+                count: apis.len(),
+            })
+        }
+    };
+
+    let backup = Backup {
+        host: host.host.clone(),
+        ..api_util::call_with_policy(&ExportCommand, host)?
+    };
+    let serialised_json =
+        serde_json::to_string_pretty(&backup).expect("Unable to serialise backup to JSON");
+    println!("{}", serialised_json);
+    Ok(exit_code::SUCCESS)
+}
+
+/// Copy the entries of one host to the hosts of the target groups
+fn run_sync(
+    opts: &PiHoleCtlOptions,
+    config: &PiHoleCtlConfig,
+    from: &str,
+    to: &[String],
+    prune: bool,
+) -> Result<i32, Error> {
+    let mut source = [config::resolve_host(
+        config,
+        from,
+        &opts.hosts,
+        &opts.keys,
+        &default_policy(opts),
+    )?];
+    route_hosts(opts, &mut source)?;
+    let [source] = source;
+
+    let read = ReadStateCommand {
+        sections: DesiredState::all_sections(),
+    };
+    let source_state = api_util::call_with_policy(&read, &source)?;
+
+    // The source may also be a member of the target groups
+    let mut apis = select_hosts(opts, config, to)?;
+    apis.retain(|host| host.host != source.host);
+    if apis.is_empty() {
+        return Err(Error::NoHosts);
+    }
+
+    let command = SyncCommand {
+        source: source_state,
+        prune,
+    };
+    Ok(call_and_display(
+        &apis,
+        opts,
+        SyncSummary::to_table_title(),
+        |host| api_util::call_with_policy(&command, host),
+    ))
+}
+
+/// Call every host and write the results in the selected output format, returning the exit code
+fn call_and_display<F, T>(
+    apis: &[PiHoleHost],
+    opts: &PiHoleCtlOptions,
+    title: Vec<TableCell>,
+    call: F,
+) -> i32
+where
+    F: Fn(&PiHoleHost) -> Result<T, HostError> + Sync,
+    T: Into<APIResult> + Send,
+{
+    let call = |host: &PiHoleHost| call(host).map(T::into);
+    let mut failed = 0;
+    let mut authentication_failed = false;
+    // Hosts which responded, but did not make every change, also count as failed
+    let mut record = |result: &Result<APIResult, HostError>| match result {
+        Ok(data) if !data.has_failures() => {}
+        Ok(_) => failed += 1,
+        Err(error) => {
+            failed += 1;
+            authentication_failed |= error.is_authentication_error();
+        }
+    };
+
+    let fail_fast = opts.fail_fast;
+    let stop = |result: &Result<_, HostError>| fail_fast && result.is_err();
+
+    if opts.output.is_streaming() {
+        let split_rows = opts.output == OutputFormat::NdjsonRows;
+        let mut called = vec![false; apis.len()];
+        api_util::call_each(apis, opts.parallelism, call, stop, |index, result| {
+            called[index] = true;
+            record(&result);
+            output::display_ndjson(&apis[index], result, split_rows);
+        });
+        for (host, _) in apis.iter().zip(called).filter(|(_, called)| !called) {
+            record(&Err(HostError::Skipped));
+            output::display_ndjson(host, Err(HostError::Skipped), split_rows);
+        }
+    } else {
+        let results: Vec<_> = api_util::call_all_until(apis, opts.parallelism, call, stop)
+            .into_iter()
+            .map(|result| result.unwrap_or(Err(HostError::Skipped)))
+            .collect();
+        results.iter().for_each(&mut record);
+
+        output::display(results, title, apis, opts.output);
+    }
+
+    exit_code::for_outcome(apis.len(), failed, authentication_failed)
+}
@@ -0,0 +1,279 @@
+use crate::api_util::{ListName, PiHoleBackend};
+use crate::table::{TableCell, ToTableCell, ToTableRows, ToTableTitle};
+use crate::validate;
+use cli_table::Color;
+use pi_hole_api::errors::APIError;
+use regex::RegexBuilder;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Lists checked by `list test` when no list is given. On a Pi-Hole the wildcard lists return
+/// the same entries as the regex lists, so entries seen twice are only reported once.
+pub const TESTED_LISTS: [ListName; 5] = [
+    ListName::White,
+    ListName::WhiteRegex,
+    ListName::WhiteWild,
+    ListName::BlackRegex,
+    ListName::BlackWild,
+];
+
+/// Which list entries match a domain on a single host, and whether it would be blocked
+#[derive(Debug, Clone, Serialize)]
+pub struct ListTest {
+    pub domain: String,
+    pub query_type: String,
+    pub verdict: Verdict,
+    pub matches: Vec<ListMatch>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// A whitelist entry matched, which wins over any blacklist entry
+    Allowed,
+    Blocked,
+    /// No entry matched. The domain may still be on the exact lists or gravity.
+    NotMatched,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ListMatch {
+    pub list: ListName,
+    pub entry: String,
+    pub effect: Effect,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    Allows,
+    Blocks,
+    /// A blacklist entry which matched, but a whitelist entry also matched
+    Overridden,
+    /// The entry could not be evaluated locally, e.g. it uses syntax the regex engine lacks
+    Unchecked,
+}
+
+/// Fetch the entries of `lists` and evaluate them against `domain` as FTL would. The exact
+/// whitelist is also fetched when a list which blocks is tested.
+pub fn test_lists(
+    api: &dyn PiHoleBackend,
+    lists: &[ListName],
+    domain: &str,
+    query_type: &str,
+) -> Result<ListTest, APIError> {
+    let api = api.get_authenticated_api()?;
+    // An exact whitelist entry wins over any blacklist entry, so it is checked with any list
+    // which may block the domain
+    let mut lists = lists.to_vec();
+    if lists.iter().any(|list| !list.is_white()) && !lists.contains(&ListName::White) {
+        lists.insert(0, ListName::White);
+    }
+
+    let mut seen = HashSet::new();
+    let mut matches = Vec::new();
+    for list in &lists {
+        for details in api.list_get_domains(list.as_str())? {
+            if !details.enabled || !seen.insert((list.is_white(), details.domain.clone())) {
+                continue;
+            }
+            let (matched, message) = if list.is_regex() || list.is_wild() {
+                match matches_regex(&details.domain, domain, query_type) {
+                    Ok(matched) => (matched, None),
+                    Err(message) => (true, Some(message)),
+                }
+            } else {
+                (details.domain.eq_ignore_ascii_case(domain), None)
+            };
+            if matched {
+                let effect = match (&message, list.is_white()) {
+                    (Some(_), _) => Effect::Unchecked,
+                    (None, true) => Effect::Allows,
+                    (None, false) => Effect::Blocks,
+                };
+                matches.push(ListMatch {
+                    list: *list,
+                    entry: details.domain,
+                    effect,
+                    message,
+                });
+            }
+        }
+    }
+
+    let verdict = if matches.iter().any(|m| m.effect == Effect::Allows) {
+        for m in matches.iter_mut().filter(|m| m.effect == Effect::Blocks) {
+            m.effect = Effect::Overridden;
+        }
+        Verdict::Allowed
+    } else if matches.iter().any(|m| m.effect == Effect::Blocks) {
+        Verdict::Blocked
+    } else {
+        Verdict::NotMatched
+    };
+    Ok(ListTest {
+        domain: domain.to_owned(),
+        query_type: query_type.to_owned(),
+        verdict,
+        matches,
+    })
+}
+
+/// Evaluate a regex list entry. Like FTL the match ignores case, `;querytype=` limits the entry
+/// to some query types, optionally negated with `!`, and `;invert` matches domains which the
+/// regex does not.
+fn matches_regex(entry: &str, domain: &str, query_type: &str) -> Result<bool, String> {
+    let (pattern, options) = validate::split_regex_options(entry);
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| validate::regex_error(&e))?;
+
+    let mut matched = regex.is_match(domain);
+    for option in options {
+        if let Some(types) = option.strip_prefix("querytype=") {
+            let (negated, types) = match types.strip_prefix('!') {
+                Some(types) => (true, types),
+                None => (false, types),
+            };
+            let listed = types
+                .split(',')
+                .any(|listed| listed.eq_ignore_ascii_case(query_type));
+            if listed == negated {
+                return Ok(false);
+            }
+        } else if option == "invert" {
+            matched = !matched;
+        }
+    }
+    Ok(matched)
+}
+
+impl ToTableRows for ListTest {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
+        let verdict = match self.verdict {
+            Verdict::Allowed => "allowed".cell().foreground_color(Some(Color::Green)),
+            Verdict::Blocked => "blocked".cell().foreground_color(Some(Color::Red)),
+            Verdict::NotMatched => "not matched".cell(),
+        };
+        if self.matches.is_empty() {
+            return vec![vec![
+                host.cell(),
+                verdict,
+                "-".cell(),
+                "-".cell(),
+                "-".cell(),
+            ]];
+        }
+        self.matches
+            .into_iter()
+            .map(|m| {
+                let effect = match m.effect {
+                    Effect::Allows => "allows".to_owned(),
+                    Effect::Blocks => "blocks".to_owned(),
+                    Effect::Overridden => "blocks, but whitelisted".to_owned(),
+                    Effect::Unchecked => {
+                        format!("not checked: {}", m.message.unwrap_or_default())
+                    }
+                };
+                vec![
+                    host.cell(),
+                    verdict.clone(),
+                    m.list.as_str().cell(),
+                    m.entry.cell(),
+                    effect.cell(),
+                ]
+            })
+            .collect()
+    }
+}
+
+impl ToTableTitle for ListTest {
+    fn to_table_title() -> Vec<TableCell> {
+        vec![
+            "Host".cell(),
+            "verdict".cell(),
+            "list".cell(),
+            "entry".cell(),
+            "effect".cell(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::FakePiHole;
+
+    #[test]
+    fn exact_whitelist_overrides_a_tested_blacklist() {
+        let fake = FakePiHole::new()
+            .with_list_domain(ListName::White, "ads.example")
+            .with_list_domain(ListName::BlackRegex, r"^ads\.");
+        let result = test_lists(&fake, &[ListName::BlackRegex], "ADS.example", "A").unwrap();
+        assert_eq!(result.verdict, Verdict::Allowed);
+        let matches: Vec<_> = result
+            .matches
+            .iter()
+            .map(|m| (m.list, m.entry.as_str(), m.effect))
+            .collect();
+        assert_eq!(
+            matches,
+            [
+                (ListName::White, "ads.example", Effect::Allows),
+                (ListName::BlackRegex, r"^ads\.", Effect::Overridden),
+            ]
+        );
+    }
+
+    fn matches(entry: &str, domain: &str, query_type: &str) -> bool {
+        matches_regex(entry, domain, query_type).unwrap()
+    }
+
+    #[test]
+    fn query_types_limit_an_entry() {
+        assert!(matches(r"^ads\.;querytype=A", "ads.example", "A"));
+        assert!(!matches(r"^ads\.;querytype=A", "ads.example", "AAAA"));
+        assert!(!matches(r"^ads\.;querytype=A", "cdn.example", "A"));
+        // Types are compared without regard to case
+        assert!(matches(r"^ads\.;querytype=a", "ADS.example", "A"));
+    }
+
+    #[test]
+    fn negated_query_types_exclude_them() {
+        assert!(!matches(r"^ads\.;querytype=!A", "ads.example", "A"));
+        assert!(matches(r"^ads\.;querytype=!A", "ads.example", "AAAA"));
+    }
+
+    #[test]
+    fn query_types_may_be_listed() {
+        let entry = r"^ads\.;querytype=A,AAAA";
+        assert!(matches(entry, "ads.example", "A"));
+        assert!(matches(entry, "ads.example", "AAAA"));
+        assert!(!matches(entry, "ads.example", "MX"));
+
+        let negated = r"^ads\.;querytype=!A,AAAA";
+        assert!(!matches(negated, "ads.example", "AAAA"));
+        assert!(matches(negated, "ads.example", "MX"));
+    }
+
+    #[test]
+    fn inverted_entries_match_other_domains() {
+        assert!(!matches(r"^ads\.;invert", "ads.example", "A"));
+        assert!(matches(r"^ads\.;invert", "cdn.example", "A"));
+        // The query type still limits an inverted entry
+        assert!(matches(r"^ads\.;querytype=A;invert", "cdn.example", "A"));
+        assert!(!matches(
+            r"^ads\.;querytype=A;invert",
+            "cdn.example",
+            "AAAA"
+        ));
+    }
+
+    #[test]
+    fn entries_the_regex_engine_lacks_are_errors() {
+        assert!(matches_regex(r"^(?!ads)", "cdn.example", "A").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::api_util::ListName;
+use clap::ArgEnum;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Fixtures bundled with the crate, covering every endpoint used by `ApiCommands`
+pub const BUNDLED_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/mock");
+
+/// Actions which the Pi-Hole API serves without an API key
+const UNAUTHENTICATED_ACTIONS: [&str; 5] = [
+    "summary",
+    "summaryRaw",
+    "overTimeData10mins",
+    "version",
+    "versions",
+];
+
+/// Minimal Pi-Hole which serves the `admin/api.php` and `admin/api_db.php` endpoints from a
+/// directory of fixture files.
+///
+/// The fixture for a request is chosen from its first query parameter:
+/// - `customdns` and `customcname` use `<endpoint>_<action>.json`, e.g. `customdns_get.json`
+/// - `add`, `sub` and `get` of a list use `list_<verb>_<list>.json`, falling back to
+///   `list_<verb>.json`, e.g. `list_get_black.json`
+/// - everything else uses `<parameter>.json`, e.g. `summary.json` or `topItems.json`
+///
+/// A fixture can hold a sequence of responses, as saved by `--record` when a run makes the same
+/// request several times. The nth request for a fixture is answered from `<name>.<n>.json` if it
+/// exists, e.g. `list_get_black.2.json`, and from `<name>.json` otherwise.
+///
+/// Like a real Pi-Hole, authenticated endpoints respond with `[]` when the `auth` parameter does
+/// not match the API key, and unknown lists respond with `Invalid list`.
+pub struct MockServer {
+    listener: TcpListener,
+    fixtures: PathBuf,
+    api_key: Option<String>,
+    /// Number of requests answered by each fixture, to serve recorded sequences in order
+    served: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl MockServer {
+    /// Bind to `address`, e.g. `127.0.0.1:0` for any free port.
+    /// Without an API key the `auth` parameter is not checked.
+    pub fn bind(address: &str, fixtures: PathBuf, api_key: Option<String>) -> io::Result<Self> {
+        Ok(MockServer {
+            listener: TcpListener::bind(address)?,
+            fixtures,
+            api_key,
+            served: Arc::default(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Address to pass to `--hosts`, e.g. http://127.0.0.1:8080
+    pub fn url(&self) -> io::Result<String> {
+        Ok(format!("http://{}", self.local_addr()?))
+    }
+
+    /// Serve requests until the process exits
+    pub fn serve(self) {
+        for stream in self.listener.incoming().flatten() {
+            let fixtures = self.fixtures.clone();
+            let api_key = self.api_key.clone();
+            let served = self.served.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &fixtures, api_key.as_deref(), &served) {
+                    eprintln!("Mock server connection failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Serve requests on a background thread, returning the address to pass to `--hosts`
+    pub fn spawn(self) -> io::Result<String> {
+        let url = self.url()?;
+        thread::spawn(move || self.serve());
+        Ok(url)
+    }
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    fixtures: &Path,
+    api_key: Option<&str>,
+    served: &Mutex<HashMap<String, usize>>,
+) -> io::Result<()> {
+    let target = read_request(&stream)?;
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let (status, body) = match path {
+        "/admin/api.php" | "/admin/api_db.php" => respond(query, fixtures, api_key, served),
+        _ => ("404 Not Found", format!("No such path {}", path)),
+    };
+    write_response(&mut stream, status, body.as_bytes())
+}
+
+/// Read a request from the API client, returning its target e.g. `/admin/api.php?summary`
+pub(crate) fn read_request(stream: &TcpStream) -> io::Result<String> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Requests from the API client have no body, so the headers can be discarded
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    Ok(request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_owned())
+}
+
+/// Write a JSON response and close the connection. `status` is the code and reason, e.g. `200 OK`
+pub(crate) fn write_response(stream: &mut TcpStream, status: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// File holding the `sequence`th response of a fixture, counting from 1, e.g. `summary.json`
+/// then `summary.2.json`
+pub(crate) fn fixture_file(name: &str, sequence: usize) -> String {
+    match sequence {
+        0 | 1 => format!("{}.json", name),
+        _ => format!("{}.{}.json", name, sequence),
+    }
+}
+
+fn respond(
+    query: &str,
+    fixtures: &Path,
+    api_key: Option<&str>,
+    served: &Mutex<HashMap<String, usize>>,
+) -> (&'static str, String) {
+    let params = parse_query(query);
+    let action = match params.first() {
+        Some((action, _)) => action.as_str(),
+        None => return ("400 Bad Request", "Missing action".to_owned()),
+    };
+
+    let auth = params.iter().find(|(key, _)| key == "auth");
+    let authenticated = match (api_key, auth) {
+        (None, _) => true,
+        (Some(expected), Some((_, auth))) => expected == auth,
+        (Some(_), None) => false,
+    };
+    if !authenticated && !UNAUTHENTICATED_ACTIONS.contains(&action) {
+        return ("200 OK", "[]".to_owned());
+    }
+
+    let candidates = match fixture_names(&params) {
+        Ok(candidates) => candidates,
+        Err(body) => return ("200 OK", body),
+    };
+    let sequence = {
+        let mut served = served.lock().expect("Mock server state was poisoned");
+        let count = served.entry(candidates[0].clone()).or_default();
+        *count += 1;
+        *count
+    };
+    for name in &candidates {
+        for file in [fixture_file(name, sequence), fixture_file(name, 1)] {
+            if let Ok(body) = std::fs::read_to_string(fixtures.join(file)) {
+                return ("200 OK", body);
+            }
+        }
+    }
+    (
+        "404 Not Found",
+        format!("No fixture {}.json for {}", candidates[0], action),
+    )
+}
+
+pub(crate) fn parse_query(query: &str) -> Vec<(String, String)> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// Names of the fixtures which answer a request, most specific first.
+/// Requests for an unknown list fail with the body a Pi-Hole responds with.
+pub(crate) fn fixture_names(params: &[(String, String)]) -> Result<Vec<String>, String> {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let action = match params.first() {
+        Some((action, _)) => action.as_str(),
+        None => return Ok(Vec::new()),
+    };
+
+    match (action, param("list")) {
+        ("customdns" | "customcname", _) => Ok(vec![format!(
+            "{}_{}",
+            action,
+            param("action").unwrap_or("get")
+        )]),
+        ("add" | "sub" | "get", Some(list)) => {
+            if ListName::from_str(list, false).is_err() {
+                return Err(format!("Invalid list [{}]", list));
+            }
+            Ok(vec![
+                format!("list_{}_{}", action, list),
+                format!("list_{}", action),
+            ])
+        }
+        _ => Ok(vec![action.to_owned()]),
+    }
+}
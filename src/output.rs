@@ -1,64 +1,361 @@
-use crate::table::ToTableRows;
-use cli_table::{CellStruct, Table};
-use pi_hole_api::errors::APIError;
+use crate::api_util::{APIResult, PiHoleHost};
+use crate::error::{ErrorReport, HostError};
+use crate::table::{to_table, TableCell, ToTableRows};
+use clap::ArgEnum;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::io::Write;
 
-pub fn display<I, H, R>(results: I, title: Vec<CellStruct>, hosts: &[H], json: bool)
-where
-    I: Iterator<Item = Result<R, APIError>>,
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable table
+    Table,
+    /// Pretty printed JSON
+    Json,
+    /// YAML document
+    Yaml,
+    /// Comma separated values using the table columns
+    Csv,
+    /// Tab separated values using the table columns
+    Tsv,
+    /// One JSON object per host, written as soon as that host responds
+    Ndjson,
+    /// As ndjson, but list results are written as one JSON object per entry
+    NdjsonRows,
+}
+
+impl OutputFormat {
+    /// Streaming formats write each result as soon as it arrives rather than waiting for every host
+    pub fn is_streaming(&self) -> bool {
+        matches!(self, Self::Ndjson | Self::NdjsonRows)
+    }
+}
+
+/// Result of a single host as written in the JSON, YAML and NDJSON output
+#[derive(Serialize)]
+struct HostResult<'a, T: Serialize> {
+    host: &'a str,
+    name: Option<&'a str>,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorReport>,
+}
+
+impl<'a, T: Serialize> HostResult<'a, T> {
+    fn new(host: &'a PiHoleHost, result: Result<T, &HostError>) -> Self {
+        let (data, error) = match result {
+            Ok(data) => (Some(data), None),
+            Err(error) => (None, Some(ErrorReport::from(error))),
+        };
+        HostResult {
+            host: &host.host,
+            name: host.name.as_deref(),
+            ok: error.is_none(),
+            data,
+            error,
+        }
+    }
+}
+
+/// Write the result of a single host as newline delimited JSON
+pub fn display_ndjson(host: &PiHoleHost, result: Result<APIResult, HostError>, split_rows: bool) {
+    let lines = match result {
+        Ok(data) => match data.into_entries() {
+            Ok(entries) if split_rows => entries
+                .into_iter()
+                .map(|entry| ndjson_line(HostResult::new(host, Ok(entry))))
+                .collect(),
+            Ok(entries) => vec![ndjson_line(HostResult::new(host, Ok(entries)))],
+            Err(data) => vec![ndjson_line(HostResult::new(host, Ok(data)))],
+        },
+        Err(error) => vec![ndjson_line(HostResult::<()>::new(host, Err(&error)))],
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    for line in lines {
+        writeln!(stdout, "{}", line).expect("Unable to write to stdout");
+    }
+}
+
+fn ndjson_line<T: Serialize>(line: HostResult<T>) -> String {
+    serde_json::to_string(&line).expect("Unable to serialise result to JSON")
+}
+
+pub fn display<R>(
+    results: Vec<Result<R, HostError>>,
+    title: Vec<TableCell>,
+    hosts: &[PiHoleHost],
+    format: OutputFormat,
+) where
     R: std::fmt::Debug + Serialize + ToTableRows,
-    H: AsRef<str>,
 {
-    if json {
-        let map: HashMap<String, Result<R, String>> = hosts
+    if format == OutputFormat::Json || format == OutputFormat::Yaml {
+        // A list keeps the hosts in the order they were given, including any duplicates
+        let document: Vec<HostResult<&R>> = hosts
             .iter()
-            .zip(results)
-            .map(|(host, result)| {
-                (
-                    host.as_ref().to_owned(),
-                    result.map_err(|e| format!("{:?}", e)),
-                )
-            })
+            .zip(&results)
+            .map(|(host, result)| HostResult::new(host, result.as_ref()))
             .collect();
-        let serialised_json =
-            serde_json::to_string_pretty(&map).expect("Unable to serialise results to JSON");
-        println!("{}", serialised_json);
-
-        // let serialised_yaml =
-        //     serde_yaml::to_string(&map).expect("Unable to serialise results to YAML");
-        // println!("{}", serialised_yaml);
+        if format == OutputFormat::Json {
+            let serialised_json = serde_json::to_string_pretty(&document)
+                .expect("Unable to serialise results to JSON");
+            println!("{}", serialised_json);
+        } else {
+            let serialised_yaml =
+                serde_yaml::to_string(&document).expect("Unable to serialise results to YAML");
+            print!("{}", serialised_yaml);
+        }
     } else {
-        let results: Vec<Result<R, APIError>> = results.collect();
-        // Separate out the errors
-        let errors = hosts
+        let (table_rows, errors) = table_rows(results, hosts);
+        write_rows(title, table_rows, format);
+        write_errors(errors);
+    }
+}
+
+/// Construct table rows from the hosts which responded, and an error line for each which did not.
+/// Hosts which responded but failed part of the command add a line for each failure after these.
+pub fn table_rows<R: ToTableRows>(
+    results: Vec<Result<R, HostError>>,
+    hosts: &[PiHoleHost],
+) -> (Vec<Vec<TableCell>>, Vec<String>) {
+    let errors = hosts
+        .iter()
+        .zip(&results)
+        .filter_map(|(host, result)| result.as_ref().err().map(|error| (host, error)))
+        .collect::<Vec<_>>();
+    let mut errors = error_lines(&errors);
+    errors.extend(
+        hosts
             .iter()
             .zip(&results)
-            .filter_map(|(host, result)| match result {
-                Ok(_) => None,
-                Err(e) => Some((host, e)),
+            .filter_map(|(host, result)| {
+                result.as_ref().ok().map(|ok| ok.failure_lines(&host.host))
             })
-            .map(|(host, error)| format!("{}: {:?}", host.as_ref(), error))
-            .collect::<Vec<String>>();
+            .flatten(),
+    );
 
-        // Construct table rows from hosts and associated results
-        let table_rows: Vec<Vec<CellStruct>> = hosts
-            .iter()
-            .zip(results)
-            .filter_map(|(host, result)| match result {
-                Ok(ok) => Some((host, ok)),
-                Err(_) => None,
-            })
-            .flat_map(|(host, response_data)| response_data.to_table_rows(host.as_ref()))
-            .collect();
-        let table = table_rows.table().title(title);
-        println!("{}", table.display().unwrap());
+    let rows = hosts
+        .iter()
+        .zip(results)
+        .filter_map(|(host, result)| result.ok().map(|ok| (host, ok)))
+        .flat_map(|(host, response_data)| response_data.to_table_rows(&host.host))
+        .collect();
+    (rows, errors)
+}
 
-        if !errors.is_empty() {
-            println!("Errors:");
-            for error in errors {
-                println!("{}", error);
+/// Write a report which spans every host, rather than one result per host.
+/// JSON and YAML write `records` as a list, NDJSON writes one record per line and the other
+/// formats write the rows. An empty table is left out.
+pub fn display_report<T: Serialize>(
+    title: Vec<TableCell>,
+    rows: Vec<Vec<TableCell>>,
+    records: Vec<T>,
+    errors: &[(&PiHoleHost, &HostError)],
+    format: OutputFormat,
+) {
+    match format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&records).expect("Unable to serialise results to JSON")
+        ),
+        OutputFormat::Yaml => print!(
+            "{}",
+            serde_yaml::to_string(&records).expect("Unable to serialise results to YAML")
+        ),
+        OutputFormat::Ndjson | OutputFormat::NdjsonRows => {
+            let mut stdout = std::io::stdout().lock();
+            for record in records {
+                let line = serde_json::to_string(&record).expect("Unable to serialise result");
+                writeln!(stdout, "{}", line).expect("Unable to write to stdout");
             }
         }
+        OutputFormat::Table if rows.is_empty() => {}
+        _ => write_rows(title, rows, format),
+    }
+    write_errors(error_lines(errors));
+}
+
+fn write_rows(title: Vec<TableCell>, rows: Vec<Vec<TableCell>>, format: OutputFormat) {
+    match format {
+        OutputFormat::Csv => write_delimited(title, rows, b','),
+        OutputFormat::Tsv => write_delimited(title, rows, b'\t'),
+        _ => {
+            let table = to_table(title, rows);
+            println!("{}", table.display().unwrap());
+        }
+    }
+}
+
+pub fn error_lines(errors: &[(&PiHoleHost, &HostError)]) -> Vec<String> {
+    errors
+        .iter()
+        .map(|(host, error)| format!("{}: {}", host.host, error))
+        .collect()
+}
+
+/// Errors are written to stderr so that stdout only contains the results
+fn write_errors(errors: Vec<String>) {
+    if !errors.is_empty() {
+        eprintln!("Errors:");
+        for error in errors {
+            eprintln!("{}", error);
+        }
+    }
+}
+
+/// Writes batches of rows as they arrive, for commands which keep running.
+/// Delimited output writes the title once, while tables are written one per batch.
+pub struct RowWriter {
+    title: Vec<TableCell>,
+    format: OutputFormat,
+    wrote_title: bool,
+}
+
+impl RowWriter {
+    pub fn new(title: Vec<TableCell>, format: OutputFormat) -> Self {
+        RowWriter {
+            title,
+            format,
+            wrote_title: false,
+        }
+    }
+
+    pub fn write(&mut self, rows: Vec<Vec<TableCell>>) {
+        let delimiter = match self.format {
+            OutputFormat::Csv => b',',
+            OutputFormat::Tsv => b'\t',
+            _ => {
+                if !rows.is_empty() {
+                    write_rows(self.title.clone(), rows, self.format);
+                }
+                return;
+            }
+        };
+        let title = (!self.wrote_title).then(|| self.title.clone());
+        self.wrote_title = true;
+        write_delimited_rows(title, rows, delimiter);
+    }
+}
+
+fn write_delimited(title: Vec<TableCell>, rows: Vec<Vec<TableCell>>, delimiter: u8) {
+    write_delimited_rows(Some(title), rows, delimiter)
+}
+
+/// Write the plain text of each cell, dropping any styling
+fn write_delimited_rows(title: Option<Vec<TableCell>>, rows: Vec<Vec<TableCell>>, delimiter: u8) {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(std::io::stdout());
+    for row in title.into_iter().chain(rows) {
+        writer
+            .write_record(row.into_iter().map(|cell| cell.text))
+            .expect("Unable to write delimited output");
+    }
+    writer.flush().expect("Unable to write delimited output");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_util::{call_hosts, CallPolicy, ListName};
+    use crate::bulk::BulkListCommand;
+    use crate::commands::{ApiCommands, ListCommands};
+    use crate::fake::FakePiHole;
+    use crate::state::Action;
+    use pi_hole_api::errors::APIError;
+    use std::sync::Arc;
+
+    fn host(name: &str, fake: FakePiHole) -> PiHoleHost {
+        PiHoleHost::with_backend(
+            format!("http://{}", name),
+            None,
+            Arc::new(fake),
+            CallPolicy::default(),
+        )
+    }
+
+    fn texts(rows: &[Vec<TableCell>]) -> Vec<Vec<&str>> {
+        rows.iter()
+            .map(|row| row.iter().map(|cell| cell.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn rows_and_errors_follow_host_order() {
+        let hosts = vec![
+            host(
+                "one",
+                FakePiHole::new()
+                    .with_list_domain(ListName::Black, "a.example")
+                    .with_list_domain(ListName::Black, "b.example"),
+            ),
+            host(
+                "broken",
+                FakePiHole::new().failing_with(|| APIError::FTLNotRunning),
+            ),
+            host(
+                "two",
+                FakePiHole::new().with_list_domain(ListName::Black, "c.example"),
+            ),
+            host("keyless", FakePiHole::new().without_key()),
+        ];
+        let command = ApiCommands::List {
+            list: Some(ListName::Black),
+            command: ListCommands::Show,
+        };
+        let results = call_hosts(&command, &hosts, 4, false);
+
+        let (rows, errors) = table_rows(results, &hosts);
+        let rows = texts(&rows);
+        let labelled: Vec<_> = rows.iter().map(|row| (row[0], row[3])).collect();
+        assert_eq!(
+            labelled,
+            [
+                ("http://one", "a.example"),
+                ("http://one", "b.example"),
+                ("http://two", "c.example"),
+            ]
+        );
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("http://broken: "));
+        assert!(errors[1].starts_with("http://keyless: "));
+    }
+
+    #[test]
+    fn hosts_without_rows_only_add_errors() {
+        let hosts = vec![host("empty", FakePiHole::new())];
+        let command = ApiCommands::List {
+            list: Some(ListName::White),
+            command: ListCommands::Show,
+        };
+        let (rows, errors) = table_rows(call_hosts(&command, &hosts, 1, false), &hosts);
+        assert!(rows.is_empty());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn failed_bulk_domains_are_error_lines() {
+        let hosts = vec![
+            host("strict", FakePiHole::new().rejecting("bad.example")),
+            host(
+                "broken",
+                FakePiHole::new().failing_with(|| APIError::FTLNotRunning),
+            ),
+        ];
+        let command = BulkListCommand {
+            list: ListName::Black,
+            action: Action::Add,
+            domains: vec!["bad.example".to_owned()],
+        };
+        let (rows, errors) = table_rows(call_hosts(&command, &hosts, 1, false), &hosts);
+        assert_eq!(texts(&rows), [["http://strict", "0", "0", "1"]]);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("http://broken: "));
+        assert_eq!(
+            errors[1],
+            "http://strict: bad.example: bad.example is not a valid domain"
+        );
     }
 }
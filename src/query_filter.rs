@@ -0,0 +1,289 @@
+use clap::{ArgEnum, Args};
+use pi_hole_api::api_types::Query;
+use pi_hole_api::ftl_types::QueryStatus;
+use regex::{Regex, RegexBuilder};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Query types which FTL reports
+pub const QUERY_TYPES: [&str; 16] = [
+    "A", "AAAA", "ANY", "SRV", "SOA", "PTR", "TXT", "NAPTR", "MX", "DS", "RRSIG", "DNSKEY", "NS",
+    "OTHER", "SVCB", "HTTPS",
+];
+
+/// Filters for `all-queries`. A query is kept if it matches every filter which is given, and any
+/// of the values of a filter which is given more than once.
+#[derive(Args, Clone, Debug, Default)]
+pub struct QueryFilter {
+    /// Only queries from a client IP or hostname. A glob such as 192.168.0.*, or a regex
+    /// between slashes
+    #[clap(long)]
+    pub client: Vec<Pattern>,
+    /// Only queries for a domain. A glob such as *.example.com, or a regex between slashes such
+    /// as /^ads?\./
+    #[clap(long)]
+    pub domain: Vec<Pattern>,
+    /// Only queries with a status
+    #[clap(long, arg_enum)]
+    pub status: Vec<StatusFilter>,
+    /// Only queries of a type
+    #[clap(long = "type", possible_values = QUERY_TYPES, ignore_case = true)]
+    pub query_type: Vec<String>,
+    /// Only queries forwarded to an upstream server, with or without its port. A glob or a regex
+    /// between slashes
+    #[clap(long)]
+    pub upstream: Vec<Pattern>,
+    /// Only queries at or after a time, either a time such as "2024-01-31 18:00:00" in UTC or a
+    /// duration before now such as 1h
+    #[clap(long, parse(try_from_str = parse_time))]
+    pub since: Option<SystemTime>,
+    /// Only queries at or before a time, either a time in UTC or a duration before now
+    #[clap(long, parse(try_from_str = parse_time))]
+    pub until: Option<SystemTime>,
+}
+
+impl QueryFilter {
+    pub fn matches(&self, query: &Query) -> bool {
+        let time =
+            UNIX_EPOCH + Duration::from_secs(query.timestring.and_utc().timestamp().max(0) as u64);
+        let query_type = format!("{:?}", query.query_type);
+        // Upstreams are reported as address#port
+        let upstream = &query.upstream_destination;
+        let upstream_address = upstream.split('#').next().unwrap_or_default();
+
+        any_or_empty(&self.client, |pattern| pattern.matches(&query.client))
+            && any_or_empty(&self.domain, |pattern| pattern.matches(&query.domain))
+            && any_or_empty(&self.status, |status| status.matches(&query.status))
+            && any_or_empty(&self.query_type, |t| t.eq_ignore_ascii_case(&query_type))
+            && any_or_empty(&self.upstream, |pattern| {
+                pattern.matches(upstream) || pattern.matches(upstream_address)
+            })
+            && self.since.is_none_or(|since| time >= since)
+            && self.until.is_none_or(|until| time <= until)
+    }
+
+    pub fn apply(&self, queries: Vec<Query>) -> Vec<Query> {
+        queries
+            .into_iter()
+            .filter(|query| self.matches(query))
+            .collect()
+    }
+}
+
+fn any_or_empty<T>(values: &[T], matches: impl Fn(&T) -> bool) -> bool {
+    values.is_empty() || values.iter().any(matches)
+}
+
+/// Groups of FTL query statuses
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusFilter {
+    /// Blocked by gravity, a regex, the blacklist or an upstream server
+    Blocked,
+    /// Answered from the cache or by an upstream server
+    Allowed,
+    Cached,
+    Forwarded,
+}
+
+impl StatusFilter {
+    pub fn matches(&self, status: &QueryStatus) -> bool {
+        use QueryStatus::*;
+        let blocked = matches!(
+            status,
+            QueryGravity
+                | QueryRegex
+                | QueryBlacklist
+                | QueryExternalBlockedIp
+                | QueryExternalBlockedNull
+                | QueryExternalBlockedNxra
+                | QueryGravityCname
+                | QueryRegexCname
+                | QueryBlacklistCname
+        );
+        let forwarded = matches!(status, QueryForwarded | QueryRetried | QueryRetriedDnssec);
+        let cached = matches!(status, QueryCache);
+        match self {
+            Self::Blocked => blocked,
+            Self::Allowed => forwarded || cached,
+            Self::Cached => cached,
+            Self::Forwarded => forwarded,
+        }
+    }
+}
+
+/// A case insensitive glob, or a regex written between slashes
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn matches(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = regex::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let regex = match pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Some(regex) => regex.to_owned(),
+            None => {
+                let glob = regex::escape(pattern)
+                    .replace("\\*", ".*")
+                    .replace("\\?", ".");
+                format!("^{}$", glob)
+            }
+        };
+        RegexBuilder::new(&regex)
+            .case_insensitive(true)
+            .build()
+            .map(Pattern)
+    }
+}
+
+/// Parse a time in UTC such as "2024-01-31 18:00:00", or a duration before now such as 1h
+fn parse_time(arg: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = arg.parse::<humantime::Duration>() {
+        return Ok(SystemTime::now() - Duration::from(ago));
+    }
+    humantime::parse_rfc3339_weak(arg).map_err(|_| {
+        format!(
+            "'{}' is neither a time such as \"2024-01-31 18:00:00\" nor a duration such as 1h",
+            arg
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A query as the Pi-Hole returns it. Status 1 is gravity, 2 forwarded and 3 cached.
+    fn query(time: u64, query_type: &str, domain: &str, status: &str, upstream: &str) -> Query {
+        serde_json::from_value(serde_json::json!([
+            time.to_string(),
+            query_type,
+            domain,
+            "192.168.0.2",
+            status,
+            "0",
+            "4",
+            "12",
+            "",
+            "-1",
+            upstream,
+            ""
+        ]))
+        .unwrap()
+    }
+
+    fn pattern(pattern: &str) -> Pattern {
+        pattern.parse().unwrap()
+    }
+
+    #[test]
+    fn globs_match_the_whole_text_ignoring_case() {
+        let subnet = pattern("192.168.0.*");
+        assert!(subnet.matches("192.168.0.42"));
+        assert!(!subnet.matches("192.168.1.42"));
+        // Dots are literal, and the glob is anchored at both ends
+        assert!(!pattern("a.example").matches("abexample"));
+        assert!(!pattern("example.com").matches("ads.example.com"));
+
+        assert!(pattern("*.Example.COM").matches("ads.example.com"));
+        assert!(pattern("ad?.example.com").matches("ads.example.com"));
+        assert!(!pattern("ad?.example.com").matches("ad.example.com"));
+        assert!(pattern("(ads)+").matches("(ads)+"));
+    }
+
+    #[test]
+    fn regexes_between_slashes_are_not_anchored() {
+        let ads = pattern(r"/^ads?\./");
+        assert!(ads.matches("ad.example.com"));
+        assert!(ads.matches("ADS.example.com"));
+        assert!(!ads.matches("bad.example.com"));
+        assert!(pattern("/tracker/").matches("eu.tracker.example"));
+        assert!("/(/".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn statuses_are_grouped() {
+        use QueryStatus::*;
+        let groups = |status: QueryStatus| {
+            [
+                StatusFilter::Blocked,
+                StatusFilter::Allowed,
+                StatusFilter::Cached,
+                StatusFilter::Forwarded,
+            ]
+            .into_iter()
+            .filter(|filter| filter.matches(&status))
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(groups(QueryGravity), [StatusFilter::Blocked]);
+        assert_eq!(groups(QueryRegexCname), [StatusFilter::Blocked]);
+        assert_eq!(groups(QueryExternalBlockedNxra), [StatusFilter::Blocked]);
+        assert_eq!(
+            groups(QueryCache),
+            [StatusFilter::Allowed, StatusFilter::Cached]
+        );
+        assert_eq!(
+            groups(QueryRetried),
+            [StatusFilter::Allowed, StatusFilter::Forwarded]
+        );
+        assert!(groups(QueryUnknown).is_empty());
+    }
+
+    #[test]
+    fn times_are_in_utc_or_before_now() {
+        assert_eq!(
+            parse_time("2024-01-31 18:00:00").unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_706_724_000)
+        );
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        let parsed = parse_time("1h").unwrap();
+        let difference = parsed
+            .duration_since(hour_ago)
+            .unwrap_or_else(|e| e.duration());
+        assert!(difference < Duration::from_secs(5));
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn upstreams_match_with_or_without_the_port() {
+        let forwarded = query(1_700_000_000, "A", "example.com", "2", "8.8.8.8#53");
+        let upstream = |upstream: &str| QueryFilter {
+            upstream: vec![pattern(upstream)],
+            ..Default::default()
+        };
+        assert!(upstream("8.8.8.8").matches(&forwarded));
+        assert!(upstream("8.8.8.8#53").matches(&forwarded));
+        assert!(upstream("8.8.*").matches(&forwarded));
+        assert!(!upstream("8.8.4.4").matches(&forwarded));
+        assert!(!upstream("8.8.8.8#5353").matches(&forwarded));
+    }
+
+    #[test]
+    fn every_filter_given_must_match() {
+        let queries = vec![
+            query(1_700_000_000, "A", "example.com", "2", "8.8.8.8#53"),
+            query(1_700_000_005, "AAAA", "ads.example.net", "1", ""),
+            query(1_700_000_010, "A", "ads.example.net", "3", ""),
+        ];
+        let filter = QueryFilter {
+            domain: vec![pattern("ads.*")],
+            query_type: vec!["a".to_owned(), "https".to_owned()],
+            since: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_005)),
+            ..Default::default()
+        };
+        let kept: Vec<_> = filter
+            .apply(queries)
+            .into_iter()
+            .map(|query| query.timestring.and_utc().timestamp())
+            .collect();
+        assert_eq!(kept, [1_700_000_010]);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::api_util::PiHoleHost;
+use crate::config::ApiKey;
+use crate::error::Error;
+use crate::mock_server::{
+    fixture_file, fixture_names, parse_query, read_request, write_response, MockServer,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Route every host through a local proxy which saves each successful response under
+/// `directory`, in a subdirectory per host. Responses are saved with the file names used by
+/// [`MockServer`], numbering repeated requests, so a recording can be replayed with
+/// [`replay_hosts`] or `piholectl-mock`.
+/// Request URLs, and so the `auth` parameter, are never saved.
+pub fn record_hosts(hosts: &mut [PiHoleHost], directory: &Path) -> Result<(), Error> {
+    for host in hosts {
+        let host_directory = host_directory(directory, &host.host);
+        let recording_error = |source| Error::Recording {
+            path: host_directory.clone(),
+            source,
+        };
+        fs::create_dir_all(&host_directory).map_err(recording_error)?;
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(recording_error)?;
+        let address = listener.local_addr().map_err(recording_error)?;
+
+        let upstream = host.host.trim_end_matches('/').to_owned();
+        let saved = Arc::new(Mutex::new(HashMap::new()));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let upstream = upstream.clone();
+                let host_directory = host_directory.clone();
+                let saved = saved.clone();
+                thread::spawn(move || {
+                    if let Err(e) = forward(stream, &upstream, &host_directory, &saved) {
+                        eprintln!("Unable to record response from {}: {}", upstream, e);
+                    }
+                });
+            }
+        });
+        host.redirect(format!("http://{}", address));
+    }
+    Ok(())
+}
+
+/// Serve each host from the responses recorded under `directory` instead of calling it
+pub fn replay_hosts(hosts: &mut [PiHoleHost], directory: &Path) -> Result<(), Error> {
+    // Keys are not recorded or checked, but without one authenticated commands fail before
+    // making a request
+    let replay_key: ApiKey = "recorded-response".parse().unwrap();
+
+    for host in hosts.iter_mut() {
+        let host_directory = host_directory(directory, &host.host);
+        if !host_directory.is_dir() {
+            return Err(Error::RecordingNotFound {
+                host: host.host.clone(),
+                path: host_directory,
+            });
+        }
+        let url = MockServer::bind("127.0.0.1:0", host_directory.clone(), None)
+            .and_then(MockServer::spawn)
+            .map_err(|source| Error::Recording {
+                path: host_directory,
+                source,
+            })?;
+
+        host.redirect_with_key(url, &replay_key);
+    }
+    Ok(())
+}
+
+/// Directory holding the recording of a host, e.g. `http_192.168.0.2_8080` for
+/// `http://192.168.0.2:8080`
+fn host_directory(directory: &Path, host: &str) -> PathBuf {
+    let name: String = host
+        .trim_end_matches('/')
+        .replace("://", "_")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    directory.join(name)
+}
+
+fn forward(
+    mut stream: TcpStream,
+    upstream: &str,
+    host_directory: &Path,
+    saved: &Mutex<HashMap<String, usize>>,
+) -> io::Result<()> {
+    let target = read_request(&stream)?;
+    let response = match ureq::get(&format!("{}{}", upstream, target)).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        // Closing the connection without a response fails the call in the same way
+        Err(ureq::Error::Transport(_)) => return Ok(()),
+    };
+    let status = format!("{} {}", response.status(), response.status_text());
+    let success = response.status() == 200;
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body)?;
+
+    if success {
+        let query = target.split_once('?').map(|(_, query)| query);
+        save(host_directory, query.unwrap_or_default(), &body, saved)?;
+    }
+    write_response(&mut stream, &status, &body)
+}
+
+/// Save a response under the fixture name of its request. Repeated requests are numbered in the
+/// order they were made, so that a replay answers them in the same order.
+fn save(
+    host_directory: &Path,
+    query: &str,
+    body: &[u8],
+    saved: &Mutex<HashMap<String, usize>>,
+) -> io::Result<()> {
+    let params = parse_query(query);
+    let name = match fixture_names(&params)
+        .ok()
+        .and_then(|names| names.into_iter().next())
+    {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    let sequence = {
+        let mut saved = saved.lock().expect("Recording state was poisoned");
+        let count = saved.entry(name.clone()).or_default();
+        *count += 1;
+        *count
+    };
+
+    // The API does not echo the key, but make sure it never reaches a recording
+    let mut body = String::from_utf8_lossy(body).into_owned();
+    if let Some((_, key)) = params.iter().find(|(param, _)| param == "auth") {
+        if !key.is_empty() {
+            body = body.replace(key.as_str(), "<redacted>");
+        }
+    }
+    fs::write(host_directory.join(fixture_file(&name, sequence)), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_util::CallPolicy;
+    use crate::api_util::{call_with_policy, APIResult};
+    use crate::commands::ApiCommands;
+
+    #[test]
+    fn replayed_hosts_keep_their_name_and_groups() {
+        let directory =
+            std::env::temp_dir().join(format!("piholectl-replay-{}", std::process::id()));
+        let host_directory = host_directory(&directory, "http://pi.lan");
+        fs::create_dir_all(&host_directory).unwrap();
+        fs::write(host_directory.join("version.json"), r#"{"version":5}"#).unwrap();
+
+        let key: ApiKey = "none".parse().unwrap();
+        let mut hosts = vec![PiHoleHost::new(
+            "http://pi.lan".to_owned(),
+            Some("pi".to_owned()),
+            &key,
+            CallPolicy::default(),
+        )];
+        hosts[0].groups = vec!["home".to_owned()];
+        replay_hosts(&mut hosts, &directory).unwrap();
+
+        assert_eq!(hosts[0].host, "http://pi.lan");
+        assert_eq!(hosts[0].name.as_deref(), Some("pi"));
+        assert_eq!(hosts[0].groups, ["home"]);
+        assert!(matches!(
+            call_with_policy(&ApiCommands::Version, &hosts[0]),
+            Ok(APIResult::VersionWrapper(_))
+        ));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn version(host: &PiHoleHost) -> u32 {
+        match call_with_policy(&ApiCommands::Version, host) {
+            Ok(APIResult::VersionWrapper(version)) => version.0,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repeated_requests_are_replayed_in_order() {
+        let directory =
+            std::env::temp_dir().join(format!("piholectl-sequence-{}", std::process::id()));
+        let fixtures = directory.join("fixtures");
+        fs::create_dir_all(&fixtures).unwrap();
+        fs::write(fixtures.join("version.json"), r#"{"version":1}"#).unwrap();
+        fs::write(fixtures.join("version.2.json"), r#"{"version":2}"#).unwrap();
+        let url = MockServer::bind("127.0.0.1:0", fixtures, None)
+            .and_then(MockServer::spawn)
+            .unwrap();
+        let key: ApiKey = "none".parse().unwrap();
+        let host = || PiHoleHost::new(url.clone(), None, &key, CallPolicy::default());
+
+        let recording = directory.join("recording");
+        let mut recorded = vec![host()];
+        record_hosts(&mut recorded, &recording).unwrap();
+        assert_eq!((version(&recorded[0]), version(&recorded[0])), (1, 2));
+        let saved = host_directory(&recording, &url);
+        assert!(saved.join("version.json").is_file());
+        assert!(saved.join("version.2.json").is_file());
+
+        // Requests beyond the recorded sequence are answered with the first response
+        let mut replayed = vec![host()];
+        replay_hosts(&mut replayed, &recording).unwrap();
+        let versions: Vec<_> = (0..3).map(|_| version(&replayed[0])).collect();
+        assert_eq!(versions, [1, 2, 1]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,638 @@
+use crate::api_util::{wildcard_regex, APIResult, CallApi, ListName, PiHoleBackend, PiHoleHost};
+use crate::error::Error;
+use crate::table::{TableCell, ToTableCell, ToTableRows, ToTableTitle};
+use crate::validate;
+use clap::ArgEnum;
+use cli_table::Color;
+use pi_hole_api::errors::APIError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Desired state of each group, keyed by the name of the group in the config file
+pub type StateFile = BTreeMap<String, DesiredState>;
+
+/// Entries a Pi-Hole should have. Only the sections which are present are managed, so a state
+/// without `dns` leaves the custom DNS records alone while `dns: []` removes all of them.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    /// Domains of each managed list, keyed by list name e.g. black or white_regex. Wildcard
+    /// lists are folded into the regex lists by [`read_state_file`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub lists: BTreeMap<ListName, BTreeSet<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<BTreeSet<DnsRecord>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cname: Option<BTreeSet<CnameRecord>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsRecord {
+    pub domain: String,
+    pub ip: IpAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CnameRecord {
+    pub domain: String,
+    pub target: String,
+}
+
+impl DnsRecord {
+    /// Check the domain, converting it to the form the Pi-Hole stores
+    pub fn validated(&self) -> Result<Self, Error> {
+        Ok(DnsRecord {
+            domain: validate::domain(&self.domain)?,
+            ip: self.ip,
+        })
+    }
+}
+
+impl CnameRecord {
+    /// Check both domains, converting them to the form the Pi-Hole stores
+    pub fn validated(&self) -> Result<Self, Error> {
+        Ok(CnameRecord {
+            domain: validate::domain(&self.domain)?,
+            target: validate::domain(&self.target)?,
+        })
+    }
+}
+
+/// Read a YAML (or JSON) state file
+pub fn read_state_file(path: &Path) -> Result<StateFile, Error> {
+    let file = File::open(path).map_err(|source| Error::StateRead {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mut state: StateFile =
+        serde_yaml::from_reader(file).map_err(|source| Error::StateParse {
+            path: path.to_owned(),
+            source,
+        })?;
+
+    for (group, desired) in &mut state {
+        desired.validate().map_err(|message| Error::StateInvalid {
+            path: path.to_owned(),
+            message: format!("group '{}' {}", group, message),
+        })?;
+        desired.fold_wildcards();
+    }
+    Ok(state)
+}
+
+impl DesiredState {
+    /// A state managing the custom DNS and CNAME records and every list except the audit log.
+    /// Wildcard entries are managed through the regex lists which hold them.
+    pub fn all_sections() -> Self {
+        DesiredState {
+            lists: ListName::value_variants()
+                .iter()
+                .filter(|list| **list != ListName::Audit && !list.is_wild())
+                .map(|list| (*list, BTreeSet::new()))
+                .collect(),
+            dns: Some(BTreeSet::new()),
+            cname: Some(BTreeSet::new()),
+        }
+    }
+
+    /// Desired state of a host, combining every group which selected it.
+    /// Hosts given with --hosts belong to no group, so they take every group in `groups`.
+    pub fn for_host(state: &StateFile, host: &PiHoleHost, groups: &[String]) -> Self {
+        let host_groups = if host.groups.is_empty() {
+            groups
+        } else {
+            &host.groups
+        };
+        let mut desired = DesiredState::default();
+        for group in host_groups {
+            if let Some(group_state) = state.get(group) {
+                desired.merge(group_state);
+            }
+        }
+        desired
+    }
+
+    /// Check every entry as it would be checked on the command line, converting domains to the
+    /// form the Pi-Hole stores. Returns the problem with the first invalid entry.
+    pub fn validate(&mut self) -> Result<(), String> {
+        for (list, entries) in self.lists.iter_mut() {
+            *entries = entries
+                .iter()
+                .map(|entry| validate::list_entry(*list, entry))
+                .collect::<Result<_, _>>()
+                .map_err(|error| format!("has an invalid {} entry: {}", list, error))?;
+        }
+        let records = |error: Error| format!("has an invalid record: {}", error);
+        if let Some(dns) = &self.dns {
+            let dns: Result<_, _> = dns.iter().map(DnsRecord::validated).collect();
+            self.dns = Some(dns.map_err(records)?);
+        }
+        if let Some(cname) = &self.cname {
+            let cname: Result<_, _> = cname.iter().map(CnameRecord::validated).collect();
+            self.cname = Some(cname.map_err(records)?);
+        }
+        Ok(())
+    }
+
+    /// Move the domains of the wildcard lists into the regex lists, in the form the Pi-Hole
+    /// stores them. The Pi-Hole returns the regex entries for either list, so managing one of
+    /// them manages both.
+    pub fn fold_wildcards(&mut self) {
+        for wild in [ListName::WhiteWild, ListName::BlackWild] {
+            if let Some(domains) = self.lists.remove(&wild) {
+                self.lists
+                    .entry(wild.stored_in())
+                    .or_default()
+                    .extend(domains.iter().map(|domain| wildcard_regex(domain)));
+            }
+        }
+    }
+
+    /// Add the entries of `other`, managing every section which either state manages
+    pub fn merge(&mut self, other: &DesiredState) {
+        for (list, domains) in &other.lists {
+            self.lists
+                .entry(*list)
+                .or_default()
+                .extend(domains.iter().cloned());
+        }
+        if let Some(records) = &other.dns {
+            self.dns
+                .get_or_insert_with(BTreeSet::new)
+                .extend(records.iter().cloned());
+        }
+        if let Some(records) = &other.cname {
+            self.cname
+                .get_or_insert_with(BTreeSet::new)
+                .extend(records.iter().cloned());
+        }
+    }
+
+    /// Read the sections of a Pi-Hole which this state manages
+    pub fn current(&self, api: &dyn PiHoleBackend) -> Result<DesiredState, APIError> {
+        let api = api.get_authenticated_api()?;
+        let mut current = DesiredState::default();
+        for list in self.lists.keys() {
+            let domains = api
+                .list_get_domains(list.as_str())?
+                .into_iter()
+                .map(|details| details.domain)
+                .collect();
+            current.lists.insert(*list, domains);
+        }
+        if self.dns.is_some() {
+            let records = api.get_custom_dns_records()?;
+            current.dns = Some(
+                records
+                    .into_iter()
+                    .map(|record| DnsRecord {
+                        domain: record.domain,
+                        ip: record.ip_address,
+                    })
+                    .collect(),
+            );
+        }
+        if self.cname.is_some() {
+            let records = api.get_custom_cname_records()?;
+            current.cname = Some(
+                records
+                    .into_iter()
+                    .map(|record| CnameRecord {
+                        domain: record.domain,
+                        target: record.target_domain,
+                    })
+                    .collect(),
+            );
+        }
+        Ok(current)
+    }
+
+    /// Changes which turn `current` into this state. Removals come first so that a record
+    /// can be replaced, and CNAME records are added after the DNS records they may point to.
+    pub fn changes_from(&self, current: &DesiredState) -> Vec<Change> {
+        let desired = self.entries();
+        let current = current.entries();
+
+        let mut removals: Vec<Change> = current
+            .difference(&desired)
+            .map(|entry| Change {
+                action: Action::Remove,
+                entry: entry.clone(),
+            })
+            .collect();
+        removals.sort_by_key(|change| std::cmp::Reverse(change.entry.rank()));
+
+        let additions = desired.difference(&current).map(|entry| Change {
+            action: Action::Add,
+            entry: entry.clone(),
+        });
+        removals.into_iter().chain(additions).collect()
+    }
+
+    fn entries(&self) -> BTreeSet<Entry> {
+        let lists = self.lists.iter().flat_map(|(list, domains)| {
+            domains.iter().map(move |domain| Entry::List {
+                list: *list,
+                domain: domain.clone(),
+            })
+        });
+        let dns = self.dns.iter().flatten().map(|record| Entry::Dns {
+            domain: record.domain.clone(),
+            ip: record.ip,
+        });
+        let cname = self.cname.iter().flatten().map(|record| Entry::Cname {
+            domain: record.domain.clone(),
+            target: record.target.clone(),
+        });
+        lists.chain(dns).chain(cname).collect()
+    }
+}
+
+/// A single list entry, DNS record or CNAME record
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Entry {
+    List { list: ListName, domain: String },
+    Dns { domain: String, ip: IpAddr },
+    Cname { domain: String, target: String },
+}
+
+impl Entry {
+    /// Order in which entries are added
+    fn rank(&self) -> u8 {
+        match self {
+            Self::List { .. } => 0,
+            Self::Dns { .. } => 1,
+            Self::Cname { .. } => 2,
+        }
+    }
+
+    /// Name of the list, or dns or cname for records
+    fn kind(&self) -> &str {
+        match self {
+            Self::List { list, .. } => list.as_str(),
+            Self::Dns { .. } => "dns",
+            Self::Cname { .. } => "cname",
+        }
+    }
+
+    fn domain(&self) -> &str {
+        match self {
+            Self::List { domain, .. } | Self::Dns { domain, .. } | Self::Cname { domain, .. } => {
+                domain
+            }
+        }
+    }
+
+    /// IP address of a DNS record or target of a CNAME record
+    fn value(&self) -> String {
+        match self {
+            Self::List { .. } => String::new(),
+            Self::Dns { ip, .. } => ip.to_string(),
+            Self::Cname { target, .. } => target.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub action: Action,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+impl Change {
+    /// Make the change on a Pi-Hole
+    pub fn apply(self, api: &dyn PiHoleBackend) -> Result<AppliedChange, APIError> {
+        let api = api.get_authenticated_api()?;
+        let response = match (&self.action, &self.entry) {
+            (Action::Add, Entry::List { list, domain }) => api.list_add(domain, list.as_str()),
+            (Action::Remove, Entry::List { list, domain }) => {
+                api.list_remove(domain, list.as_str())
+            }
+            (Action::Add, Entry::Dns { domain, ip }) => api.add_custom_dns_record(ip, domain),
+            (Action::Remove, Entry::Dns { domain, ip }) => api.delete_custom_dns_record(ip, domain),
+            (Action::Add, Entry::Cname { domain, target }) => {
+                api.add_custom_cname_record(domain, target)
+            }
+            (Action::Remove, Entry::Cname { domain, target }) => {
+                api.delete_custom_cname_record(domain, target)
+            }
+        }?;
+        Ok(AppliedChange {
+            change: self,
+            success: response.success,
+            message: response.message,
+        })
+    }
+
+    fn cells(&self, host: &str) -> Vec<TableCell> {
+        let color = match self.action {
+            Action::Add => Color::Green,
+            Action::Remove => Color::Red,
+        };
+        let action = match self.action {
+            Action::Add => "add",
+            Action::Remove => "remove",
+        };
+        vec![
+            host.cell(),
+            action.cell().foreground_color(Some(color)),
+            self.entry.kind().cell(),
+            self.entry.domain().cell(),
+            self.entry.value().cell(),
+        ]
+    }
+}
+
+impl ToTableRows for Change {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
+        vec![self.cells(host)]
+    }
+}
+
+impl ToTableTitle for Change {
+    fn to_table_title() -> Vec<TableCell> {
+        vec![
+            "Host".cell(),
+            "action".cell(),
+            "type".cell(),
+            "domain".cell(),
+            "value".cell(),
+        ]
+    }
+}
+
+/// A change made on a Pi-Hole and the response to it
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedChange {
+    #[serde(flatten)]
+    pub change: Change,
+    pub success: bool,
+    pub message: Option<String>,
+}
+
+impl ToTableRows for AppliedChange {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
+        let mut row = self.change.cells(host);
+        row.push(self.success.cell());
+        row.push(self.message.unwrap_or_default().cell());
+        vec![row]
+    }
+}
+
+impl ToTableTitle for AppliedChange {
+    fn to_table_title() -> Vec<TableCell> {
+        let mut title = Change::to_table_title();
+        title.push("success".cell());
+        title.push("message".cell());
+        title
+    }
+}
+
+/// Plans, and optionally makes, the changes needed for a host to reach its desired state
+pub struct StateCommand {
+    pub desired: DesiredState,
+    pub apply: bool,
+}
+
+impl CallApi for StateCommand {
+    type Output = APIResult;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<APIResult, APIError> {
+        let current = self.desired.current(api)?;
+        let changes = self.desired.changes_from(&current);
+        if !self.apply {
+            return Ok(APIResult::Changes(changes));
+        }
+
+        // Stop at the first error, a later plan shows the changes which were not made
+        changes
+            .into_iter()
+            .map(|change| change.apply(api))
+            .collect::<Result<Vec<_>, _>>()
+            .map(APIResult::AppliedChanges)
+    }
+}
+
+/// Reads the sections of a Pi-Hole which `sections` manages
+pub struct ReadStateCommand {
+    pub sections: DesiredState,
+}
+
+impl CallApi for ReadStateCommand {
+    type Output = DesiredState;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<DesiredState, APIError> {
+        self.sections.current(api)
+    }
+}
+
+/// Copies the entries of a source Pi-Hole to a replica
+pub struct SyncCommand {
+    pub source: DesiredState,
+    /// Remove entries which the source does not have
+    pub prune: bool,
+}
+
+impl CallApi for SyncCommand {
+    type Output = SyncSummary;
+
+    fn call(&self, api: &dyn PiHoleBackend) -> Result<SyncSummary, APIError> {
+        let current = self.source.current(api)?;
+        let unchanged = self
+            .source
+            .entries()
+            .intersection(&current.entries())
+            .count();
+        let changes = self
+            .source
+            .changes_from(&current)
+            .into_iter()
+            .filter(|change| self.prune || change.action == Action::Add)
+            .map(|change| change.apply(api))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let made = |action| {
+            changes
+                .iter()
+                .filter(|applied| applied.success && applied.change.action == action)
+                .count()
+        };
+        Ok(SyncSummary {
+            added: made(Action::Add),
+            removed: made(Action::Remove),
+            unchanged,
+            failed: changes.iter().filter(|applied| !applied.success).count(),
+            changes,
+        })
+    }
+}
+
+/// Counts of the changes made on a replica, along with the changes themselves
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncSummary {
+    pub added: usize,
+    pub removed: usize,
+    /// Entries which the replica already had
+    pub unchanged: usize,
+    /// Changes which the replica did not accept
+    pub failed: usize,
+    pub changes: Vec<AppliedChange>,
+}
+
+impl ToTableRows for SyncSummary {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
+        let failed_color = if self.failed > 0 {
+            Some(Color::Red)
+        } else {
+            None
+        };
+        vec![vec![
+            host.cell(),
+            self.added.cell(),
+            self.removed.cell(),
+            self.unchanged.cell(),
+            self.failed.cell().foreground_color(failed_color),
+        ]]
+    }
+}
+
+impl ToTableTitle for SyncSummary {
+    fn to_table_title() -> Vec<TableCell> {
+        vec![
+            "Host".cell(),
+            "added".cell(),
+            "removed".cell(),
+            "unchanged".cell(),
+            "failed".cell(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::FakePiHole;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn try_read(yaml: &str) -> Result<StateFile, Error> {
+        // Tests run in parallel, so each read needs its own file
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "piholectl-state-{}-{}.yaml",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, yaml).unwrap();
+        let state = read_state_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        state
+    }
+
+    fn read(yaml: &str) -> StateFile {
+        try_read(yaml).unwrap()
+    }
+
+    fn plan(desired: &DesiredState, api: &dyn PiHoleBackend) -> Vec<Change> {
+        desired.changes_from(&desired.current(api).unwrap())
+    }
+
+    #[test]
+    fn wildcards_are_managed_in_regex_form() {
+        let state = read(
+            r#"
+office:
+  lists:
+    white_wild: [example.com]
+    white_regex: ['(\.|^)example\.com$', '^ads\.']
+"#,
+        );
+        let desired = &state["office"];
+        assert_eq!(
+            desired.lists.keys().collect::<Vec<_>>(),
+            [&ListName::WhiteRegex]
+        );
+        assert_eq!(desired.lists[&ListName::WhiteRegex].len(), 2);
+
+        let fake = FakePiHole::new();
+        let apply = StateCommand {
+            desired: desired.clone(),
+            apply: true,
+        };
+        apply.call(&fake).unwrap();
+        assert!(plan(desired, &fake).is_empty());
+    }
+
+    #[test]
+    fn wildcards_added_through_the_wild_list_match_the_state() {
+        let state = read("office:\n  lists:\n    black_wild: [tracker.example.org]\n");
+        let fake = FakePiHole::new();
+        fake.get_authenticated_api()
+            .unwrap()
+            .list_add("tracker.example.org", "black_wild")
+            .unwrap();
+        assert!(plan(&state["office"], &fake).is_empty());
+    }
+
+    #[test]
+    fn rejected_changes_are_failures() {
+        let state = read("office:\n  lists:\n    black: [ads.example, bad.example]\n");
+        let fake = FakePiHole::new().rejecting("bad.example");
+        let apply = StateCommand {
+            desired: state["office"].clone(),
+            apply: true,
+        };
+        let result = apply.call(&fake).unwrap();
+        match &result {
+            APIResult::AppliedChanges(changes) => {
+                let outcomes: Vec<_> = changes
+                    .iter()
+                    .map(|applied| (applied.change.entry.domain(), applied.success))
+                    .collect();
+                assert_eq!(outcomes, [("ads.example", true), ("bad.example", false)]);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(result.has_failures());
+    }
+
+    #[test]
+    fn entries_are_checked_when_read() {
+        let state = read("office:\n  lists:\n    black: [bücher.example]\n  cname:\n    - {domain: files.lan, target: nas.lan}\n");
+        assert!(state["office"].lists[&ListName::Black].contains("xn--bcher-kva.example"));
+
+        for yaml in [
+            "office:\n  lists:\n    black: ['not a domain']\n",
+            "office:\n  lists:\n    white_wild: ['(\\\\.|^)example\\\\.com$']\n",
+            "office:\n  dns:\n    - {domain: 'bad domain', ip: 192.168.0.10}\n",
+        ] {
+            match try_read(yaml) {
+                Err(Error::StateInvalid { message, .. }) => {
+                    assert!(
+                        message.starts_with("group 'office' has an invalid"),
+                        "{}",
+                        message
+                    )
+                }
+                other => panic!("unexpected result {:?} for {}", other, yaml),
+            }
+        }
+        assert!(matches!(
+            try_read("office:\n  lists:\n    grey: []\n"),
+            Err(Error::StateParse { .. })
+        ));
+    }
+}
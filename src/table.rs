@@ -1,12 +1,67 @@
-use cli_table::{Cell, CellStruct, Color, Style, Table, TableStruct};
+use cli_table::{CellStruct, Color, Style, Table, TableStruct};
 use pi_hole_api::api_types::*;
+use std::fmt::Display;
+
+/// A cell which can be rendered either into a styled table or as plain text
+#[derive(Debug, Clone)]
+pub struct TableCell {
+    pub text: String,
+    pub color: Option<Color>,
+}
+
+impl TableCell {
+    pub fn foreground_color(mut self, color: Option<Color>) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn into_cell_struct(self) -> CellStruct {
+        cli_table::Cell::cell(self.text).foreground_color(self.color)
+    }
+}
+
+pub trait ToTableCell {
+    fn cell(self) -> TableCell;
+}
+
+impl<T> ToTableCell for T
+where
+    T: Display,
+{
+    fn cell(self) -> TableCell {
+        TableCell {
+            text: self.to_string(),
+            color: None,
+        }
+    }
+}
+
+/// Convert rows of cells into a styled table
+pub fn to_table(title: Vec<TableCell>, rows: Vec<Vec<TableCell>>) -> TableStruct {
+    rows.into_iter()
+        .map(|row| row.into_iter().map(TableCell::into_cell_struct).collect())
+        .collect::<Vec<Vec<CellStruct>>>()
+        .table()
+        .title(
+            title
+                .into_iter()
+                .map(TableCell::into_cell_struct)
+                .collect::<Vec<CellStruct>>(),
+        )
+}
 
 pub trait ToTableRows {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>>;
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>>;
+
+    /// Details of what failed on a host which responded, written to stderr alongside the errors
+    /// when the rows only show counts
+    fn failure_lines(&self, _host: &str) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 impl ToTableRows for AllQueries {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.data
             .into_iter()
             .flat_map(|query| query.to_table_rows(host))
@@ -15,7 +70,7 @@ impl ToTableRows for AllQueries {
 }
 
 impl ToTableRows for CacheInfo {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.cache_size.cell(),
@@ -26,13 +81,13 @@ impl ToTableRows for CacheInfo {
 }
 
 impl ToTableRows for ClientName {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![host.cell(), self.name.cell(), self.ip.cell()]]
     }
 }
 
 impl ToTableRows for CustomCNAMERecord {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.domain.cell(),
@@ -42,7 +97,7 @@ impl ToTableRows for CustomCNAMERecord {
 }
 
 impl ToTableRows for CustomDNSRecord {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.domain.cell(),
@@ -52,7 +107,7 @@ impl ToTableRows for CustomDNSRecord {
 }
 
 impl ToTableRows for CustomListDomainDetails {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.id.cell(),
@@ -68,7 +123,7 @@ impl ToTableRows for CustomListDomainDetails {
 }
 
 impl ToTableRows for ForwardDestinations {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.forward_destinations
             .into_iter()
             .map(|(ip, percentage)| vec![host.cell(), ip.cell(), percentage.cell()])
@@ -77,7 +132,7 @@ impl ToTableRows for ForwardDestinations {
 }
 
 impl ToTableRows for ListModificationResponse {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.success.cell(),
@@ -87,7 +142,7 @@ impl ToTableRows for ListModificationResponse {
 }
 
 impl ToTableRows for Network {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.network
             .into_iter()
             .flat_map(|network_client| network_client.to_table_rows(host))
@@ -96,7 +151,7 @@ impl ToTableRows for Network {
 }
 
 impl ToTableRows for NetworkClient {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.id.cell(),
@@ -113,7 +168,7 @@ impl ToTableRows for NetworkClient {
 }
 
 impl ToTableRows for OverTimeData {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         let domains_over_time = self
             .domains_over_time
             .into_iter()
@@ -132,7 +187,7 @@ impl ToTableRows for OverTimeData {
 }
 
 impl ToTableRows for Query {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.timestring.cell(),
@@ -152,7 +207,7 @@ impl ToTableRows for Query {
 }
 
 impl ToTableRows for QueryTypes {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.querytypes
             .into_iter()
             .map(|(query_type, percentage)| vec![host.cell(), query_type.cell(), percentage.cell()])
@@ -161,7 +216,7 @@ impl ToTableRows for QueryTypes {
 }
 
 impl ToTableRows for Status {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             string_status_to_colored_cell(&self.status),
@@ -170,7 +225,7 @@ impl ToTableRows for Status {
 }
 
 impl ToTableRows for Summary {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.domains_being_blocked.cell(),
@@ -194,7 +249,7 @@ impl ToTableRows for Summary {
 }
 
 impl ToTableRows for SummaryRaw {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.domains_being_blocked.cell(),
@@ -218,7 +273,7 @@ impl ToTableRows for SummaryRaw {
 }
 
 impl ToTableRows for TopClients {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.top_sources
             .into_iter()
             .map(|(ip, count)| vec![host.cell(), ip.cell(), count.cell()])
@@ -227,7 +282,7 @@ impl ToTableRows for TopClients {
 }
 
 impl ToTableRows for TopClientsBlocked {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.top_sources_blocked
             .into_iter()
             .map(|(ip, count)| vec![host.cell(), ip.cell(), count.cell()])
@@ -236,7 +291,7 @@ impl ToTableRows for TopClientsBlocked {
 }
 
 impl ToTableRows for TopItems {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         let top_queries = self.top_queries.into_iter().map(|(domain, count)| {
             vec![
                 host.cell(),
@@ -259,13 +314,13 @@ impl ToTableRows for TopItems {
 }
 
 impl ToTableRows for Version {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![host.cell(), self.version.cell()]]
     }
 }
 
 impl ToTableRows for Versions {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         vec![vec![
             host.cell(),
             self.core_update.cell(),
@@ -288,14 +343,14 @@ impl<T> ToTableRows for Vec<T>
 where
     T: ToTableRows,
 {
-    fn to_table_rows(self, host: &str) -> Vec<Vec<CellStruct>> {
+    fn to_table_rows(self, host: &str) -> Vec<Vec<TableCell>> {
         self.into_iter()
             .flat_map(|item| item.to_table_rows(host))
             .collect()
     }
 }
 
-fn string_status_to_colored_cell(status: &str) -> CellStruct {
+fn string_status_to_colored_cell(status: &str) -> TableCell {
     let color = if status == "enabled" {
         Color::Green
     } else {
@@ -305,21 +360,21 @@ fn string_status_to_colored_cell(status: &str) -> CellStruct {
 }
 
 pub trait ToTableTitleDynamic {
-    fn to_table_title(&self) -> Vec<CellStruct>;
+    fn to_table_title(&self) -> Vec<TableCell>;
 }
 
 pub trait ToTableTitle {
-    fn to_table_title() -> Vec<CellStruct>;
+    fn to_table_title() -> Vec<TableCell>;
 }
 
 impl ToTableTitle for AllQueries {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         Query::to_table_title()
     }
 }
 
 impl ToTableTitle for CacheInfo {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec![
             "Host".cell(),
             "cache_size".cell(),
@@ -330,25 +385,25 @@ impl ToTableTitle for CacheInfo {
 }
 
 impl ToTableTitle for ClientName {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "name".cell(), "ip".cell()]
     }
 }
 
 impl ToTableTitle for CustomCNAMERecord {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "domain".cell(), "target_domain".cell()]
     }
 }
 
 impl ToTableTitle for CustomDNSRecord {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "domain".cell(), "ip_address".cell()]
     }
 }
 
 impl ToTableTitle for CustomListDomainDetails {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec![
             "Host".cell(),
             "id".cell(),
@@ -364,25 +419,25 @@ impl ToTableTitle for CustomListDomainDetails {
 }
 
 impl ToTableTitle for ForwardDestinations {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "Name/IP".cell(), "Percentage".cell()]
     }
 }
 
 impl ToTableTitle for ListModificationResponse {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "success".cell(), "message".cell()]
     }
 }
 
 impl ToTableTitle for Network {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         NetworkClient::to_table_title()
     }
 }
 
 impl ToTableTitle for NetworkClient {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec![
             "Host".cell(),
             "id".cell(),
@@ -399,13 +454,13 @@ impl ToTableTitle for NetworkClient {
 }
 
 impl ToTableTitle for OverTimeData {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "type".cell(), "time".cell(), "count".cell()]
     }
 }
 
 impl ToTableTitle for Query {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec![
             "Host".cell(),
             "timestring".cell(),
@@ -425,19 +480,19 @@ impl ToTableTitle for Query {
 }
 
 impl ToTableTitle for QueryTypes {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "type".cell(), "percentage".cell()]
     }
 }
 
 impl ToTableTitle for Status {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "status".cell()]
     }
 }
 
 impl ToTableTitle for Summary {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec![
             "Host".cell(),
             "domains_being_blocked".cell(),
@@ -461,25 +516,25 @@ impl ToTableTitle for Summary {
 }
 
 impl ToTableTitle for SummaryRaw {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         Summary::to_table_title()
     }
 }
 
 impl ToTableTitle for TopClients {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec!["Host".cell(), "Hostname/IP".cell(), "count".cell()]
     }
 }
 
 impl ToTableTitle for TopClientsBlocked {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         TopClients::to_table_title()
     }
 }
 
 impl ToTableTitle for TopItems {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec![
             "Host".cell(),
             "type".cell(),
@@ -490,7 +545,7 @@ impl ToTableTitle for TopItems {
 }
 
 impl ToTableTitle for Versions {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         vec![
             "Host".cell(),
             "core_update".cell(),
@@ -513,7 +568,7 @@ impl<T> ToTableTitle for Vec<T>
 where
     T: ToTableTitle,
 {
-    fn to_table_title() -> Vec<CellStruct> {
+    fn to_table_title() -> Vec<TableCell> {
         T::to_table_title()
     }
 }
@@ -531,6 +586,6 @@ where
             .into_iter()
             .flat_map(|(host, response_data)| response_data.to_table_rows(&host))
             .collect();
-        rows.table().title(T::to_table_title())
+        to_table(T::to_table_title(), rows)
     }
 }
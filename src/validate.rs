@@ -0,0 +1,167 @@
+use crate::api_util::ListName;
+use crate::error::Error;
+use regex::Regex;
+use regex_syntax::ast::parse::Parser;
+use regex_syntax::ast::ErrorKind;
+
+/// Longest domain the DNS allows, excluding the trailing dot
+const MAX_DOMAIN_LENGTH: usize = 253;
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Options which FTL accepts after a regex e.g. `^ads\.;querytype=A`
+const REGEX_OPTIONS: [&str; 3] = ["querytype=", "invert", "reply="];
+
+/// Check an entry for a list before it is sent, returning the form the Pi-Hole stores.
+/// Regex lists take a regular expression and every other list takes a domain.
+pub fn list_entry(list: ListName, entry: &str) -> Result<String, Error> {
+    if list.is_regex() {
+        regex(entry)
+    } else {
+        domain(entry)
+    }
+}
+
+/// Check the syntax of a domain, converting internationalised domains to punycode.
+/// Underscores are accepted as the Pi-Hole accepts them.
+pub fn domain(domain: &str) -> Result<String, Error> {
+    let invalid = |message: String| Error::InvalidEntry {
+        entry: domain.to_owned(),
+        message,
+    };
+
+    let ascii = idna::domain_to_ascii(domain)
+        .map_err(|e| invalid(format!("not a valid internationalised domain ({})", e)))?;
+    if ascii.is_empty() {
+        return Err(invalid("the domain is empty".to_owned()));
+    }
+    if ascii.len() > MAX_DOMAIN_LENGTH {
+        return Err(invalid(format!(
+            "domains are at most {} characters",
+            MAX_DOMAIN_LENGTH
+        )));
+    }
+    for label in ascii.split('.') {
+        if label.is_empty() {
+            return Err(invalid("the domain has an empty label".to_owned()));
+        }
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(invalid(format!(
+                "label '{}' is longer than {} characters",
+                label, MAX_LABEL_LENGTH
+            )));
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+        {
+            return Err(invalid(format!("'{}' is not allowed in a domain", c)));
+        }
+    }
+    Ok(ascii)
+}
+
+/// Check a regex, ignoring any FTL options after it. FTL uses POSIX regexes, which allow
+/// backreferences and look-around that the local regex engine lacks, so a regex using them is sent
+/// as given with a warning. Any other regex which does not parse is rejected.
+pub fn regex(entry: &str) -> Result<String, Error> {
+    let (pattern, _) = split_regex_options(entry);
+    let invalid = |message: String| Error::InvalidEntry {
+        entry: entry.to_owned(),
+        message,
+    };
+    if pattern.is_empty() {
+        return Err(invalid("the regex is empty".to_owned()));
+    }
+    if let Err(e) = Regex::new(pattern) {
+        if !uses_posix_only_syntax(pattern) {
+            return Err(invalid(regex_error(&e)));
+        }
+        eprintln!(
+            "Unable to check regex {} locally, sending it as given: {}",
+            entry,
+            regex_error(&e)
+        );
+    }
+    Ok(entry.to_owned())
+}
+
+/// Whether a pattern fails to parse because it uses syntax which FTL accepts but the local regex
+/// engine does not support
+fn uses_posix_only_syntax(pattern: &str) -> bool {
+    match Parser::new().parse(pattern) {
+        Ok(_) => false,
+        Err(e) => matches!(
+            e.kind(),
+            ErrorKind::UnsupportedBackreference | ErrorKind::UnsupportedLookAround
+        ),
+    }
+}
+
+/// The problem named by a regex parse error, on one line. The other lines of the error point at
+/// the part of the pattern with the problem.
+pub fn regex_error(error: &regex::Error) -> String {
+    let message = error.to_string();
+    let problem = message.lines().last().unwrap_or_default();
+    problem.trim_start_matches("error: ").to_owned()
+}
+
+/// Split a regex list entry into the pattern and the FTL options which follow it,
+/// e.g. `^ads\.;querytype=A;invert` into `^ads\.` and `["querytype=A", "invert"]`
+pub fn split_regex_options(entry: &str) -> (&str, Vec<&str>) {
+    let mut pattern = entry;
+    let mut options = Vec::new();
+    while let Some((rest, option)) = pattern.rsplit_once(';') {
+        if !REGEX_OPTIONS.iter().any(|known| option.starts_with(known)) {
+            break;
+        }
+        options.insert(0, option);
+        pattern = rest;
+    }
+    (pattern, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regexes_the_local_engine_lacks_are_accepted() {
+        assert_eq!(regex(r"^(ads)\1\.").unwrap(), r"^(ads)\1\.");
+        assert_eq!(regex(r"^ads\.;querytype=A").unwrap(), r"^ads\.;querytype=A");
+        assert_eq!(regex(r"^(?=ads)").unwrap(), r"^(?=ads)");
+        assert!(matches!(
+            regex(";querytype=A"),
+            Err(Error::InvalidEntry { .. })
+        ));
+    }
+
+    #[test]
+    fn regexes_which_do_not_parse_are_rejected() {
+        let unclosed = format!("^({}", "ads");
+        match regex(&unclosed) {
+            Err(Error::InvalidEntry { entry, message }) => {
+                assert_eq!(entry, "^(ads");
+                assert_eq!(message, "unclosed group");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(regex(&format!("[z-a]{}", ";invert")).is_err());
+    }
+
+    #[test]
+    fn regex_errors_are_one_line() {
+        let pattern = format!(r"^(ads)\{}", 1);
+        let error = Regex::new(&pattern).unwrap_err();
+        assert_eq!(regex_error(&error), "backreferences are not supported");
+    }
+
+    #[test]
+    fn domains_are_stored_as_punycode() {
+        assert_eq!(domain("Bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert!(domain("two..dots").is_err());
+        assert_eq!(
+            list_entry(ListName::BlackWild, "ads.example").unwrap(),
+            "ads.example"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::table::{to_table, TableCell};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+/// Redraws a table in place on each refresh, adding the change since the previous refresh to
+/// numeric cells.
+///
+/// Rows are matched across refreshes by their cells which are not numbers, such as the host and
+/// domain, so a row which moves within a top list still shows its own change.
+pub struct Watch {
+    interval: Duration,
+    title: Vec<TableCell>,
+    previous: Vec<Vec<TableCell>>,
+    drawn: bool,
+}
+
+impl Watch {
+    pub fn new(interval: Duration, title: Vec<TableCell>) -> Self {
+        Watch {
+            interval,
+            title,
+            previous: Vec::new(),
+            drawn: false,
+        }
+    }
+
+    /// Draw the rows and errors of a refresh over the previous one
+    pub fn refresh(&mut self, rows: Vec<Vec<TableCell>>, errors: Vec<String>) {
+        let shown = with_deltas(&rows, &self.previous);
+        self.previous = rows;
+
+        let mut frame = format!(
+            "Every {}, last refreshed {}\n\n",
+            humantime::format_duration(self.interval),
+            humantime::format_rfc3339_seconds(SystemTime::now())
+        );
+        frame.push_str(
+            &to_table(self.title.clone(), shown)
+                .display()
+                .unwrap()
+                .to_string(),
+        );
+        frame.push('\n');
+        if !errors.is_empty() {
+            frame.push_str("\nErrors:\n");
+            for error in errors {
+                frame.push_str(&error);
+                frame.push('\n');
+            }
+        }
+
+        // Clearing the whole screen between refreshes flickers, so each line is overwritten
+        // and only what is left of the previous frame is cleared
+        let mut stdout = std::io::stdout().lock();
+        let start = if self.drawn {
+            "\x1b[H"
+        } else {
+            "\x1b[2J\x1b[H"
+        };
+        let mut screen = String::from(start);
+        for line in frame.lines() {
+            screen.push_str(line);
+            screen.push_str("\x1b[K\n");
+        }
+        screen.push_str("\x1b[J");
+        stdout
+            .write_all(screen.as_bytes())
+            .and_then(|_| stdout.flush())
+            .expect("Unable to write to stdout");
+        self.drawn = true;
+    }
+}
+
+/// Append the change since `previous` to each numeric cell of `rows` which has changed
+pub fn with_deltas(rows: &[Vec<TableCell>], previous: &[Vec<TableCell>]) -> Vec<Vec<TableCell>> {
+    let mut earlier: HashMap<(String, usize), &Vec<TableCell>> = HashMap::new();
+    for (key, row) in row_keys(previous).into_iter().zip(previous) {
+        earlier.insert(key, row);
+    }
+
+    row_keys(rows)
+        .into_iter()
+        .zip(rows)
+        .map(|(key, row)| match earlier.get(&key) {
+            Some(before) if before.len() == row.len() => row
+                .iter()
+                .zip(before.iter())
+                .map(|(cell, before)| with_delta(cell, before))
+                .collect(),
+            _ => row.clone(),
+        })
+        .collect()
+}
+
+/// Identify each row by the text of its cells which are not numbers. Rows which share this
+/// text are told apart by the order they appear in.
+fn row_keys(rows: &[Vec<TableCell>]) -> Vec<(String, usize)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    rows.iter()
+        .map(|row| {
+            let key = row
+                .iter()
+                .map(|cell| match parse_number(&cell.text) {
+                    Some(_) => "",
+                    None => cell.text.as_str(),
+                })
+                .collect::<Vec<_>>()
+                .join("\u{1f}");
+            let occurrence = seen.entry(key.clone()).or_default();
+            *occurrence += 1;
+            (key, *occurrence)
+        })
+        .collect()
+}
+
+fn with_delta(cell: &TableCell, before: &TableCell) -> TableCell {
+    let (now, then) = match (parse_number(&cell.text), parse_number(&before.text)) {
+        (Some(now), Some(then)) if now != then => (now, then),
+        _ => return cell.clone(),
+    };
+    // Show the change with as many decimal places as the value itself
+    let decimals = cell
+        .text
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len());
+    TableCell {
+        text: format!("{} ({:+.*})", cell.text, decimals, now - then),
+        color: cell.color,
+    }
+}
+
+/// Parse a number as shown in a table, which may be grouped with commas e.g. 1,234
+fn parse_number(text: &str) -> Option<f64> {
+    let text = text.replace(',', "");
+    if text.is_empty()
+        || !text
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        return None;
+    }
+    text.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::ToTableCell;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<TableCell>> {
+        rows.iter()
+            .map(|row| row.iter().map(|text| text.cell()).collect())
+            .collect()
+    }
+
+    fn texts(rows: &[Vec<TableCell>]) -> Vec<Vec<&str>> {
+        rows.iter()
+            .map(|row| row.iter().map(|cell| cell.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_matched_after_a_top_list_reorders() {
+        let previous = rows(&[&["pi", "a.example", "40"], &["pi", "b.example", "30"]]);
+        let current = rows(&[&["pi", "b.example", "45"], &["pi", "a.example", "41"]]);
+        assert_eq!(
+            texts(&with_deltas(&current, &previous)),
+            [
+                ["pi", "b.example", "45 (+15)"],
+                ["pi", "a.example", "41 (+1)"]
+            ]
+        );
+    }
+
+    #[test]
+    fn rows_sharing_their_text_are_matched_in_order() {
+        let previous = rows(&[&["pi", "1"], &["pi", "5"]]);
+        let current = rows(&[&["pi", "2"], &["pi", "5"], &["pi", "7"]]);
+        assert_eq!(
+            texts(&with_deltas(&current, &previous)),
+            [["pi", "2 (+1)"], ["pi", "5"], ["pi", "7"]]
+        );
+    }
+
+    #[test]
+    fn grouped_numbers_show_their_change() {
+        let previous = rows(&[&["pi", "10,375"]]);
+        let current = rows(&[&["pi", "10,412"]]);
+        assert_eq!(
+            texts(&with_deltas(&current, &previous)),
+            [["pi", "10,412 (+37)"]]
+        );
+    }
+
+    #[test]
+    fn decimal_changes_keep_the_precision_of_the_value() {
+        let previous = rows(&[&["pi", "12.50", "3.1"]]);
+        let current = rows(&[&["pi", "12.25", "3.4"]]);
+        assert_eq!(
+            texts(&with_deltas(&current, &previous)),
+            [["pi", "12.25 (-0.25)", "3.4 (+0.3)"]]
+        );
+    }
+
+    #[test]
+    fn addresses_are_not_numbers() {
+        assert_eq!(parse_number("192.168.0.2"), None);
+        assert_eq!(parse_number("1,234"), Some(1234.0));
+        assert_eq!(parse_number(""), None);
+
+        // A client's address identifies its row rather than being compared
+        let previous = rows(&[&["192.168.0.2", "10"], &["192.168.0.3", "20"]]);
+        let current = rows(&[&["192.168.0.3", "25"], &["192.168.0.2", "10"]]);
+        assert_eq!(
+            texts(&with_deltas(&current, &previous)),
+            [["192.168.0.3", "25 (+5)"], ["192.168.0.2", "10"]]
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Runs the `piholectl` binary against the mock Pi-Hole served on an ephemeral port
+
+use piholectl::mock_server::{MockServer, BUNDLED_FIXTURES};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const API_KEY: &str = "0123456789abcdef";
+
+fn mock() -> String {
+    MockServer::bind(
+        "127.0.0.1:0",
+        PathBuf::from(BUNDLED_FIXTURES),
+        Some(API_KEY.to_owned()),
+    )
+    .and_then(MockServer::spawn)
+    .expect("Unable to start the mock server")
+}
+
+/// Mock Pi-Hole which answers every list addition with a failure, and the directory of its
+/// fixtures. `name` keeps the fixtures of each test apart.
+fn rejecting_mock(name: &str) -> (String, PathBuf) {
+    let fixtures =
+        std::env::temp_dir().join(format!("piholectl-cli-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&fixtures).unwrap();
+    for file in std::fs::read_dir(BUNDLED_FIXTURES).unwrap() {
+        let file = file.unwrap();
+        std::fs::copy(file.path(), fixtures.join(file.file_name())).unwrap();
+    }
+    std::fs::write(
+        fixtures.join("list_add.json"),
+        r#"{"success":false,"message":"Domain is not allowed"}"#,
+    )
+    .unwrap();
+    let url = MockServer::bind("127.0.0.1:0", fixtures.clone(), Some(API_KEY.to_owned()))
+        .and_then(MockServer::spawn)
+        .expect("Unable to start the mock server");
+    (url, fixtures)
+}
+
+/// Address on which nothing is listening
+fn unreachable() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+/// Run piholectl with a home directory of its own, so that no config file is picked up
+fn piholectl(args: &[&str]) -> Output {
+    let home = std::env::temp_dir().join(format!("piholectl-cli-{}", std::process::id()));
+    Command::new(env!("CARGO_BIN_EXE_piholectl"))
+        .args(args)
+        .env("HOME", &home)
+        .env("XDG_CONFIG_HOME", home.join(".config"))
+        .output()
+        .expect("Unable to run piholectl")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn summary_is_written_as_a_table() {
+    let url = mock();
+    let output = piholectl(&["--hosts", &url, "--keys", "none", "summary-raw"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).contains(&url));
+    assert!(stdout(&output).contains("123456"));
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn json_output_has_one_result_per_host() {
+    let url = mock();
+    let output = piholectl(&[
+        "--hosts", &url, "--hosts", &url, "--keys", "none", "--keys", "none", "-o", "json",
+        "version",
+    ]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let expected = serde_json::json!({"host": url, "name": null, "ok": true, "data": 3});
+    assert_eq!(results, serde_json::json!([expected, expected]));
+}
+
+#[test]
+fn authenticated_command_uses_the_key() {
+    let url = mock();
+    let output = piholectl(&["--hosts", &url, "--keys", API_KEY, "list", "black", "show"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn rejected_key_is_an_authentication_error() {
+    let url = mock();
+    let output = piholectl(&["--hosts", &url, "--keys", "wrong-key-0123", "top-items"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains(&format!("{}: ", url)));
+}
+
+#[test]
+fn unreachable_host_fails() {
+    let url = unreachable();
+    let output = piholectl(&["--hosts", &url, "--keys", "none", "summary"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("Errors:\n"));
+    assert!(stderr(&output).contains(&url));
+    // One readable line per host rather than the structure of the error
+    assert_eq!(stderr(&output).lines().count(), 2, "{}", stderr(&output));
+    assert!(!stderr(&output).contains("Transport {"));
+}
+
+#[test]
+fn failure_of_some_hosts_is_partial() {
+    let (url, down) = (mock(), unreachable());
+    let output = piholectl(&[
+        "--hosts", &url, "--hosts", &down, "--keys", "none", "--keys", "none", "-o", "csv",
+        "version",
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stdout(&output).contains(&format!("{},3", url)));
+    assert!(!stdout(&output).contains(&down));
+    assert!(stderr(&output).contains(&down));
+}
+
+#[test]
+fn invalid_arguments_are_a_usage_error() {
+    let output = piholectl(&[
+        "--hosts",
+        "http://127.0.0.1:1",
+        "--keys",
+        "none",
+        "disable",
+        "soon",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).is_empty());
+}
+
+#[test]
+fn bulk_changes_which_do_not_stick_fail_the_run() {
+    let url = mock();
+    let file = std::env::temp_dir().join(format!("piholectl-bulk-{}.txt", std::process::id()));
+    std::fs::write(&file, "ads.example.net\ntracker.example.org\n").unwrap();
+    let output = piholectl(&[
+        "--hosts",
+        &url,
+        "--keys",
+        API_KEY,
+        "-o",
+        "json",
+        "list",
+        "black",
+        "add",
+        "--file",
+        file.to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&file).unwrap();
+
+    // The mock accepts the add, but its list never changes
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let summary = &results[0]["data"];
+    assert_eq!(summary["unchanged"], 1);
+    assert_eq!(
+        summary["failed_domains"],
+        serde_json::json!([{"domain": "tracker.example.org", "message": "not on the list after adding"}])
+    );
+}
+
+#[test]
+fn aggregate_totals_the_hosts_which_responded() {
+    let (first, second, down) = (mock(), mock(), unreachable());
+    let output = piholectl(&[
+        "--hosts",
+        &first,
+        "--hosts",
+        &second,
+        "--hosts",
+        &down,
+        "--keys",
+        API_KEY,
+        "--keys",
+        API_KEY,
+        "--keys",
+        API_KEY,
+        "-o",
+        "json",
+        "--aggregate",
+        "top-items",
+        "1",
+    ]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    let totals: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        totals,
+        serde_json::json!([{
+            "hosts": [first, second],
+            "top_queries": [{"name": "example.com", "count": 240}],
+            "top_ads": [{"name": "ads.example.net", "count": 84}],
+        }])
+    );
+    assert!(stderr(&output).contains(&down));
+}
+
+#[test]
+fn regex_which_does_not_parse_is_not_sent() {
+    let url = unreachable();
+    let output = piholectl(&[
+        "--hosts",
+        &url,
+        "--keys",
+        API_KEY,
+        "list",
+        "black_regex",
+        "add",
+        "^(ads",
+    ]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(stderr(&output).contains("unclosed group"));
+    assert!(!stderr(&output).contains(&url));
+}
+
+#[test]
+fn imports_which_the_host_rejects_fail_the_run() {
+    let (url, fixtures) = rejecting_mock("import");
+    let file = std::env::temp_dir().join(format!("piholectl-import-{}.json", std::process::id()));
+    std::fs::write(
+        &file,
+        r#"{"host": "", "max_logage": 24.0, "dns": [], "cname": [],
+            "lists": {"black": [{"domain": "tracker.example.org", "enabled": true, "comment": ""}]}}"#,
+    )
+    .unwrap();
+    let output = piholectl(&[
+        "--hosts",
+        &url,
+        "--keys",
+        API_KEY,
+        "-o",
+        "json",
+        "import",
+        file.to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&file).unwrap();
+    std::fs::remove_dir_all(&fixtures).unwrap();
+
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let summary = &results[0]["data"];
+    assert_eq!(
+        (&summary["added"], &summary["failed"]),
+        (&0.into(), &1.into())
+    );
+}
+
+#[test]
+fn hosts_without_a_group_are_planned_against_every_group() {
+    let url = mock();
+    let file = std::env::temp_dir().join(format!("piholectl-plan-{}.yaml", std::process::id()));
+    std::fs::write(
+        &file,
+        "office:\n  lists:\n    black: [tracker.example.org]\n",
+    )
+    .unwrap();
+    let output = piholectl(&[
+        "--hosts",
+        &url,
+        "--keys",
+        API_KEY,
+        "-o",
+        "json",
+        "plan",
+        "-f",
+        file.to_str().unwrap(),
+    ]);
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        results[0]["data"],
+        serde_json::json!([
+            {"action": "remove", "type": "list", "list": "black", "domain": "ads.example.net"},
+            {"action": "add", "type": "list", "list": "black", "domain": "tracker.example.org"},
+        ])
+    );
+}